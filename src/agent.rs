@@ -0,0 +1,317 @@
+use crate::files;
+use crate::secret::SecretBytes;
+use crate::session::Session;
+use crate::vault::{Entry, LockedVault};
+use anyhow::{anyhow, Context, Result};
+use openssl::base64::{decode_block, encode_block};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use zeroize::{Zeroize, Zeroizing};
+
+/// Environment variable naming the socket of a running agent.
+pub const SOCK_VAR: &str = "RPW_AGENT_SOCK";
+/// How long a client may take to send its request or read the response.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "lowercase")]
+pub enum Request {
+    /// Hand the agent the derived key of a vault.
+    Unlock {
+        vault: String,
+        key: String,
+    },
+    Get {
+        vault: String,
+        alias: String,
+    },
+    List {
+        vault: String,
+    },
+    Add {
+        vault: String,
+        alias: String,
+        entry: Entry,
+    },
+    Remove {
+        vault: String,
+        alias: String,
+    },
+}
+
+impl Request {
+    pub fn unlock(vault: &str, key: &SecretBytes) -> Self {
+        Request::Unlock {
+            vault: vault.to_string(),
+            key: encode_block(key),
+        }
+    }
+
+    pub fn vault(&self) -> &str {
+        match self {
+            Request::Unlock { vault, .. }
+            | Request::Get { vault, .. }
+            | Request::List { vault }
+            | Request::Add { vault, .. }
+            | Request::Remove { vault, .. } => vault,
+        }
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        if let Request::Unlock { key, .. } = self {
+            key.zeroize();
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "lowercase")]
+pub enum Response {
+    Ok,
+    /// The agent does not hold the key of the vault.
+    Locked,
+    Entry {
+        entry: Option<Entry>,
+    },
+    /// The entries of a vault, without their secrets.
+    List {
+        entries: Vec<(String, Entry)>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Socket of the agent given by `RPW_AGENT_SOCK`.
+pub fn socket() -> Option<PathBuf> {
+    std::env::var_os(SOCK_VAR)
+        .filter(|s| !s.is_empty())
+        .map(PathBuf::from)
+}
+
+pub fn default_socket() -> Result<PathBuf> {
+    files::rpwd_path("agent.sock")
+}
+
+/// Send `request` to the agent listening on `sock`.
+pub fn request(sock: &Path, request: &Request) -> Result<Response> {
+    let mut stream = UnixStream::connect(sock)
+        .with_context(|| format!("Failed to connect to the agent at {}", sock.display()))?;
+    let mut line = Zeroizing::new(serde_json::to_string(request)?);
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut reply = Zeroizing::new(String::new());
+    BufReader::new(stream).read_line(&mut reply)?;
+    match serde_json::from_str(&reply).context("Malformed response from the agent")? {
+        Response::Error { message } => Err(anyhow!(message)),
+        response => Ok(response),
+    }
+}
+
+/// Listen on `path`, only accessible by the user, replacing the socket of
+/// an agent which is no longer running. Any other file is left alone.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(anyhow!("{} exists and is not a socket", path.display()));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(anyhow!(
+                "An agent is already listening on {}",
+                path.display()
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    if let Some(dir) = path.parent() {
+        files::create_private_dir(dir)?;
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to listen on {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Move the agent to the background, returning the pid of the agent in
+/// the calling process and `None` in the agent itself.
+pub fn daemonize() -> Result<Option<i32>> {
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error()).context("Failed to fork the agent"),
+        0 => {
+            unsafe {
+                libc::setsid();
+                let null = libc::open(b"/dev/null\0".as_ptr() as *const libc::c_char, libc::O_RDWR);
+                if null >= 0 {
+                    libc::dup2(null, 0);
+                    libc::dup2(null, 1);
+                    libc::dup2(null, 2);
+                }
+            }
+            Ok(None)
+        }
+        pid => Ok(Some(pid)),
+    }
+}
+
+struct Cached {
+    session: Session,
+    expires: Instant,
+}
+
+type Cache = Arc<Mutex<HashMap<String, Cached>>>;
+
+/// Serve requests on `listener`, forgetting keys `ttl` after they were
+/// handed to the agent.
+pub fn serve(listener: UnixListener, ttl: Duration, backups: usize) -> Result<()> {
+    let cache: Cache = Arc::new(Mutex::new(HashMap::new()));
+
+    let expiry = cache.clone();
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(1));
+        let now = Instant::now();
+        expiry.lock().unwrap().retain(|_, c| c.expires > now);
+    });
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        if !same_user(&stream) {
+            continue;
+        }
+        // A misbehaving client must neither take the agent down nor keep it
+        // from serving others.
+        let cache = cache.clone();
+        thread::spawn(move || {
+            let _ = handle(stream, &cache, ttl, backups);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn same_user(stream: &UnixStream) -> bool {
+    use std::os::unix::io::AsRawFd;
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    ret == 0 && cred.uid == unsafe { libc::geteuid() }
+}
+
+/// Elsewhere the permissions of the socket keep other users out.
+#[cfg(not(target_os = "linux"))]
+fn same_user(_stream: &UnixStream) -> bool {
+    true
+}
+
+fn handle(stream: UnixStream, cache: &Cache, ttl: Duration, backups: usize) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut line = Zeroizing::new(String::new());
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => respond(&request, cache, ttl, backups).unwrap_or_else(|e| Response::Error {
+            message: format!("{:#}", e),
+        }),
+        Err(e) => Response::Error {
+            message: format!("Malformed request: {}", e),
+        },
+    };
+
+    let mut reply = Zeroizing::new(serde_json::to_string(&response)?);
+    reply.push('\n');
+    (&stream).write_all(reply.as_bytes())?;
+    Ok(())
+}
+
+fn respond(request: &Request, cache: &Cache, ttl: Duration, backups: usize) -> Result<Response> {
+    let mut cache = cache.lock().unwrap();
+
+    if let Request::Unlock { vault, key } = request {
+        let lv: LockedVault = vault.parse().context("Could not find vault")?;
+        let key = SecretBytes::new(decode_block(key).context("Failed to decode key")?);
+        let session = Session::new(&lv, key, None)?;
+        let expires = Instant::now() + ttl;
+        cache.insert(vault.clone(), Cached { session, expires });
+        return Ok(Response::Ok);
+    }
+
+    let session = match cache.get_mut(request.vault()) {
+        Some(c) => &mut c.session,
+        None => return Ok(Response::Locked),
+    };
+    // A key gone stale by re-keying the vault is dropped, for the client to
+    // unlock it again.
+    if session.refresh().is_err() {
+        cache.remove(request.vault());
+        return Ok(Response::Locked);
+    }
+
+    Ok(match request {
+        Request::Get { alias, .. } => Response::Entry {
+            entry: session.vault.get(alias.clone()).cloned(),
+        },
+        Request::List { .. } => Response::List {
+            entries: session
+                .vault
+                .pws
+                .iter()
+                .map(|(id, e)| (id.clone(), without_secrets(e)))
+                .collect(),
+        },
+        Request::Add { alias, entry, .. } => {
+            let mut uv = session.vault.clone();
+            uv.insert(alias.clone(), entry.clone());
+            session.save(uv, backups)?;
+            Response::Ok
+        }
+        Request::Remove { alias, .. } => {
+            let mut uv = session.vault.clone();
+            if uv.remove(alias.clone()).is_some() {
+                session.save(uv, backups)?;
+            }
+            Response::Ok
+        }
+        Request::Unlock { .. } => unreachable!(),
+    })
+}
+
+/// `entry` with only what listing a vault shows.
+fn without_secrets(entry: &Entry) -> Entry {
+    let mut e = Entry::new(String::new());
+    e.username = entry.username.clone();
+    e.urls = entry.urls.clone();
+    e.tags = entry.tags.clone();
+    e.fields = entry
+        .fields
+        .keys()
+        .map(|k| (k.clone(), String::new()))
+        .collect();
+    e.created = entry.created;
+    e.modified = entry.modified;
+    e
+}
@@ -1,66 +1,260 @@
-use clap::{App, Arg, SubCommand};
+use crate::error::Kind;
+use anyhow::{anyhow, Context, Result};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::fs::File;
 use std::io::stdin;
 use std::io::stdout;
-use std::io::Write;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::mem::ManuallyDrop;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::process::{Command, Stdio};
 use std::string::String;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use zeroize::Zeroizing;
 
-pub fn error(msg: &str) {
-    println!("Error: {}", msg);
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+static NO_INPUT: AtomicBool = AtomicBool::new(false);
+static PASSWORD_COMMAND: Mutex<Option<String>> = Mutex::new(None);
+
+/// Environment variable naming the vault whose password `password_command`
+/// should print.
+pub const VAULT_VAR: &str = "RPW_VAULT";
+
+/// Answer confirmations with yes, and with `no_input` fail instead of
+/// prompting for anything else.
+pub fn set_noninteractive(assume_yes: bool, no_input: bool) {
+    ASSUME_YES.store(assume_yes, Ordering::Relaxed);
+    NO_INPUT.store(no_input, Ordering::Relaxed);
 }
 
-pub fn yesorno(msg: &str) -> bool {
+pub fn yesorno(msg: &str) -> Result<bool> {
+    if ASSUME_YES.load(Ordering::Relaxed) {
+        return Ok(true);
+    }
+    if NO_INPUT.load(Ordering::Relaxed) {
+        return Err(Kind::InputRequired.error(format!("{} Pass --yes to confirm", msg)));
+    }
+
     let mut ans = String::new();
     stdout()
         .write_all(format!("{} [y/n] ", msg).as_bytes())
         .expect("Failed writing to stdout");
     stdout().flush().expect("Failed to flush stdout");
-    stdin()
-        .read_line(&mut ans)
-        .expect("Failed reading from stdin");
-
-    match ans.to_ascii_lowercase().replace('\n', "").as_str() {
-        "y" | "yes" => true,
-        "n" | "no" => false,
-        _ => yesorno("Please eneter y or n"),
+    if stdin().read_line(&mut ans)? == 0 {
+        return Err(Kind::InputRequired.error(format!("{} No answer given", msg)));
+    }
+
+    match ans.to_ascii_lowercase().trim() {
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => yesorno("Please enter y or n"),
     }
 }
-pub fn password(msg: &str) -> String {
-    rpassword::prompt_password_stdout(msg).unwrap()
+
+pub fn password(msg: &str) -> Result<Zeroizing<String>> {
+    if NO_INPUT.load(Ordering::Relaxed) {
+        return Err(
+            Kind::InputRequired.error("No password given and prompting is disabled by --no-input")
+        );
+    }
+    // Prompts must not end up in the output of piped commands, such as
+    // the answers to git.
+    if stdout().is_terminal() {
+        return Ok(Zeroizing::new(rpassword::prompt_password_stdout(msg)?));
+    }
+    let pass = rpassword::read_password_from_tty(Some(msg))
+        .map_err(|_| Kind::InputRequired.error("No terminal to prompt for the password on"))?;
+    Ok(Zeroizing::new(pass))
 }
 
-pub mod xclip {
-    use std::io::prelude::*;
-    use std::process::Command;
+/// Run `cmd` for vault passwords which are not given by the arguments.
+pub fn set_password_command(cmd: Option<String>) {
+    *PASSWORD_COMMAND.lock().unwrap() = cmd;
+}
 
-    #[cfg(target_os = "macos")]
-    pub fn to_clipboard(s: &str) {
-        let mut clip = Command::new("pbcopy")
-            .stdin(std::process::Stdio::piped())
-            .spawn()
-            .expect("Failed getting pw");
+/// Whether the arguments give the vault password, directly or by where to
+/// read it from.
+pub fn password_given(args: &ArgMatches) -> bool {
+    ["password", "password-stdin", "password-fd", "password-file"]
+        .iter()
+        .any(|a| args.is_present(a))
+}
 
-        clip.stdin
-            .as_mut()
-            .unwrap()
-            .write_all(s.as_bytes())
-            .expect("Failed to open stdin");
+/// The password of `vault` read from stdin, a file descriptor or a file
+/// as the arguments ask, or else printed by the configured password
+/// command. `None` when it has to be prompted for.
+pub fn read_password(args: &ArgMatches, vault: &str) -> Result<Option<Zeroizing<String>>> {
+    if let Some(pass) = given_password(args)? {
+        return Ok(Some(pass));
     }
+    let cmd = match PASSWORD_COMMAND.lock().unwrap().clone() {
+        Some(cmd) => cmd,
+        None => return Ok(None),
+    };
+    non_empty(run_password_command(&cmd, vault)?, "password_command").map(Some)
+}
+
+/// The password read from stdin, a file descriptor or a file as the
+/// arguments ask. Unlike `read_password` the password command is not run,
+/// as it only knows the passwords of existing vaults.
+pub fn given_password(args: &ArgMatches) -> Result<Option<Zeroizing<String>>> {
+    let (pass, source) = if args.is_present("password-stdin") {
+        (first_line(stdin().lock())?, String::from("stdin"))
+    } else if let Some(fd) = args.value_of("password-fd") {
+        let fd = password_fd(fd)?;
+        let pass = first_line(BufReader::new(&*fd))
+            .with_context(|| format!("Failed to read the password from fd {}", fd.as_raw_fd()))?;
+        (pass, format!("fd {}", fd.as_raw_fd()))
+    } else if let Some(path) = args.value_of("password-file") {
+        let f =
+            File::open(path).with_context(|| format!("Failed to open password file {}", path))?;
+        (first_line(BufReader::new(f))?, path.to_string())
+    } else {
+        return Ok(None);
+    };
+    non_empty(pass, &source).map(Some)
+}
+
+fn non_empty(pass: Zeroizing<String>, source: &str) -> Result<Zeroizing<String>> {
+    if pass.is_empty() {
+        return Err(Kind::Usage.error(format!("No password read from {}", source)));
+    }
+    Ok(pass)
+}
+
+/// The file of descriptor `fd`, left open for the caller which owns it.
+/// The standard streams are refused, `--password-stdin` reads stdin.
+fn password_fd(fd: &str) -> Result<ManuallyDrop<File>> {
+    let fd: RawFd = fd
+        .parse()
+        .ok()
+        .filter(|fd| *fd > 2)
+        .ok_or_else(|| Kind::Usage.error(format!("Invalid file descriptor '{}'", fd)))?;
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(Kind::Usage.error(format!("File descriptor {} is not open", fd)));
+    }
+    Ok(ManuallyDrop::new(unsafe { File::from_raw_fd(fd) }))
+}
+
+fn first_line(mut reader: impl BufRead) -> Result<Zeroizing<String>> {
+    let mut line = Zeroizing::new(String::new());
+    reader.read_line(&mut line)?;
+    let len = line.trim_end_matches(&['\r', '\n'][..]).len();
+    line.truncate(len);
+    Ok(line)
+}
+
+fn run_password_command(cmd: &str, vault: &str) -> Result<Zeroizing<String>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env(VAULT_VAR, vault)
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run password_command '{}'", cmd))?;
+
+    let mut output = Zeroizing::new(Vec::new());
+    child.stdout.take().unwrap().read_to_end(&mut output)?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("password_command '{}' failed, {}", cmd, status));
+    }
+    let output = std::str::from_utf8(&output).context("password_command printed invalid UTF-8")?;
+    first_line(output.as_bytes())
+}
+
+fn keyfile_arg() -> Arg<'static, 'static> {
+    Arg::with_name("keyfile")
+        .long("keyfile")
+        .takes_value(true)
+        .help("File required to unlock the vault along with or instead of its password")
+}
+
+fn sec_arg() -> Arg<'static, 'static> {
+    Arg::with_name("sec")
+        .long("sec")
+        .takes_value(true)
+        .help("Seconds until the clipboard is cleared, instead of clear_copy_timeout")
+}
+
+/// Where to read the vault password from instead of prompting for it.
+fn password_source_args() -> Vec<Arg<'static, 'static>> {
+    let sources = ["password", "password-stdin", "password-fd", "password-file"];
+    let others = |name| {
+        sources
+            .iter()
+            .copied()
+            .filter(move |s| *s != name)
+            .collect::<Vec<_>>()
+    };
+    vec![
+        Arg::with_name("password-stdin")
+            .long("password-stdin")
+            .conflicts_with_all(&others("password-stdin"))
+            .help("Read the vault password from the first line of stdin"),
+        Arg::with_name("password-fd")
+            .long("password-fd")
+            .takes_value(true)
+            .value_name("FD")
+            .conflicts_with_all(&others("password-fd"))
+            .help("Read the vault password from the first line of file descriptor FD"),
+        Arg::with_name("password-file")
+            .long("password-file")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with_all(&others("password-file"))
+            .help("Read the vault password from the first line of FILE"),
+    ]
+}
+
+fn generator_args() -> Vec<Arg<'static, 'static>> {
+    let classes = [
+        ("no-lowercase", "min-lowercase"),
+        ("no-uppercase", "min-uppercase"),
+        ("no-digits", "min-digits"),
+        ("no-symbols", "min-symbols"),
+    ];
+    let mut args = vec![
+        Arg::with_name("length")
+            .long("length")
+            .short("l")
+            .takes_value(true),
+        Arg::with_name("exclude-ambiguous")
+            .long("exclude-ambiguous")
+            .help("Leave out easily confused characters such as 0 and O"),
+    ];
 
-    #[cfg(target_os = "linux")]
-    pub fn to_clipboard(s: &str) {
-        let mut clip = Command::new("xclip")
-            .stdin(std::process::Stdio::piped())
-            .arg("-selection")
-            .arg("clipboard")
-            .spawn()
-            .expect("Failed getting pw");
-
-        clip.stdin
-            .as_mut()
-            .unwrap()
-            .write_all(s.as_bytes())
-            .expect("Failed to open stdin");
+    for (no, min) in classes.iter() {
+        args.push(Arg::with_name(no).long(no));
+        args.push(Arg::with_name(min).long(min).takes_value(true));
     }
+
+    args.push(
+        Arg::with_name("passphrase")
+            .long("passphrase")
+            .help("Generate a passphrase of random words instead"),
+    );
+    args.push(Arg::with_name("words").long("words").takes_value(true));
+    args.push(
+        Arg::with_name("separator")
+            .long("separator")
+            .takes_value(true),
+    );
+    args.push(Arg::with_name("capitalize").long("capitalize"));
+    args.push(
+        Arg::with_name("digit")
+            .long("digit")
+            .help("Append a random digit to one of the words"),
+    );
+    args.push(
+        Arg::with_name("wordlist")
+            .long("wordlist")
+            .value_name("LIST")
+            .help("long or short for the EFF wordlists, or a file with one word per line")
+            .takes_value(true),
+    );
+    args
 }
 
 pub fn build() -> clap::App<'static, 'static> {
@@ -70,30 +264,270 @@ pub fn build() -> clap::App<'static, 'static> {
         .about(
             "rpw is a small cli-only password manager for your terminal
             copy pasting needs.",
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print the outcome of the command as JSON"),
+        )
+        .arg(
+            Arg::with_name("yes")
+                .long("yes")
+                .short("y")
+                .help("Answer yes to every confirmation"),
+        )
+        .arg(
+            Arg::with_name("no-input")
+                .long("no-input")
+                .help("Never prompt, failing when input would be needed"),
+        )
+        .arg(
+            Arg::with_name("home")
+                .long("home")
+                .takes_value(true)
+                .help("Directory of the vaults and configuration, overrides RPW_HOME"),
         );
 
     app = app.subcommand(
         SubCommand::with_name("open")
             .about("Open a password encrypted vault.")
-            .arg(Arg::with_name("vault").required(true).takes_value(true)),
+            .arg(Arg::with_name("vault").required(true).takes_value(true))
+            .arg(
+                Arg::with_name("password")
+                    .long("password")
+                    .short("p")
+                    .takes_value(true),
+            )
+            .args(&password_source_args())
+            .arg(keyfile_arg()),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("restore")
+            .about("List the backups of a vault, or roll the vault back to one of them.")
+            .arg(
+                Arg::with_name("vault")
+                    .long("vault")
+                    .short("v")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("backup")
+                    .help("Number or timestamp of the backup to restore")
+                    .takes_value(true),
+            ),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("lock")
+            .about("Lock the open vault, its password is required for the next command."),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("unlock")
+            .about("Cache the key of a vault in the kernel keyring, skipping the password for a while.")
+            .arg(
+                Arg::with_name("vault")
+                    .long("vault")
+                    .short("v")
+                    .required(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("password")
+                    .long("password")
+                    .short("p")
+                    .takes_value(true),
+            )
+            .args(&password_source_args())
+            .arg(keyfile_arg())
+            .arg(
+                Arg::with_name("ttl")
+                    .long("ttl")
+                    .short("t")
+                    .takes_value(true)
+                    .help("Seconds to cache the key, keyring_ttl of the configuration by default"),
+            ),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("forget")
+            .about("Remove the cached key of a vault from the kernel keyring.")
+            .arg(
+                Arg::with_name("vault")
+                    .long("vault")
+                    .short("v")
+                    .takes_value(true),
+            ),
     );
 
     app = app.subcommand(
         SubCommand::with_name("clear")
-            .about("Clear the clipboard register.")
-            .arg(Arg::with_name("sec").takes_value(true).required(true)),
+            .about("Clear the clipboard register after the given seconds.")
+            .arg(Arg::with_name("sec").takes_value(true).required(true))
+            .arg(
+                Arg::with_name("restore")
+                    .long("restore")
+                    .help("Only remove the secret described on stdin, restoring what it replaced"),
+            ),
     );
 
     app = app.subcommand(
         SubCommand::with_name("get")
-            .about("Decrypt the vault and fetch a password to the clipboard.")
-            .arg(Arg::with_name("vault").long("vault").short("v"))
+            .about("Decrypt the vault and fetch a password to the clipboard or stdout.")
+            .arg(
+                Arg::with_name("vault")
+                    .long("vault")
+                    .short("v")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("password")
+                    .long("password")
+                    .short("p")
+                    .takes_value(true),
+            )
+            .args(&password_source_args())
+            .arg(keyfile_arg())
+            .arg(Arg::with_name("alias").required(true).takes_value(true))
+            .arg(
+                Arg::with_name("field")
+                    .long("field")
+                    .short("f")
+                    .help("Entry field to fetch instead of the password")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("stdout")
+                    .long("stdout")
+                    .help("Print the value without a trailing newline instead of copying it"),
+            )
+            .arg(
+                Arg::with_name("force")
+                    .long("force")
+                    .requires("stdout")
+                    .help("Print the value even when stdout is a terminal"),
+            )
+            .arg(sec_arg().conflicts_with("stdout")),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("run")
+            .about("Run a command with secrets of the vault in its environment.")
+            .arg(
+                Arg::with_name("vault")
+                    .long("vault")
+                    .short("v")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name("password")
                     .long("password")
                     .short("p")
                     .takes_value(true),
             )
+            .args(&password_source_args())
+            .arg(keyfile_arg())
+            .arg(
+                Arg::with_name("env")
+                    .long("env")
+                    .short("e")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name("VAR=ALIAS[/FIELD]")
+                    .help("Set VAR to the password or a field of an entry"),
+            )
+            .arg(
+                Arg::with_name("mapping")
+                    .long("mapping")
+                    .short("m")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .help("File with one VAR=ALIAS[/FIELD] per line"),
+            )
+            .arg(
+                Arg::with_name("command")
+                    .required(true)
+                    .multiple(true)
+                    .last(true),
+            ),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("inject")
+            .about(
+                "Fill the {{ rpw://vault/alias/field }} placeholders of a template with secrets.",
+            )
+            .arg(
+                Arg::with_name("input")
+                    .long("input")
+                    .short("i")
+                    .required(true)
+                    .takes_value(true)
+                    .value_name("TEMPLATE"),
+            )
+            .arg(
+                Arg::with_name("output")
+                    .long("output")
+                    .short("o")
+                    .required(true)
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .help("File written with the secrets, only readable by you"),
+            )
+            .arg(
+                Arg::with_name("password")
+                    .long("password")
+                    .short("p")
+                    .takes_value(true),
+            )
+            .args(&password_source_args())
+            .arg(keyfile_arg()),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("git-credential")
+            .about("Act as git credential helper, with the credentials of git_vault or --vault.")
+            .arg(
+                Arg::with_name("operation")
+                    .required(true)
+                    .help("get, store or erase, as passed by git"),
+            )
+            .arg(
+                Arg::with_name("vault")
+                    .long("vault")
+                    .short("v")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("password")
+                    .long("password")
+                    .short("p")
+                    .takes_value(true),
+            )
+            .args(&password_source_args())
+            .arg(keyfile_arg()),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("otp")
+            .about("Compute the current one-time code of an entry and copy it to the clipboard.")
+            .arg(
+                Arg::with_name("vault")
+                    .long("vault")
+                    .short("v")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("password")
+                    .long("password")
+                    .short("p")
+                    .takes_value(true),
+            )
+            .args(&password_source_args())
+            .arg(keyfile_arg())
+            .arg(sec_arg())
             .arg(Arg::with_name("alias").required(true).takes_value(true)),
     );
 
@@ -111,6 +545,21 @@ pub fn build() -> clap::App<'static, 'static> {
                     .long("password")
                     .short("p")
                     .takes_value(true),
+            )
+            .args(&password_source_args())
+            .arg(keyfile_arg())
+            .arg(
+                Arg::with_name("long")
+                    .long("long")
+                    .short("l")
+                    .help("Show the fields of each entry, never the secrets"),
+            )
+            .arg(
+                Arg::with_name("tag")
+                    .long("tag")
+                    .short("t")
+                    .help("Only list entries with this tag")
+                    .takes_value(true),
             ),
     );
 
@@ -129,7 +578,29 @@ pub fn build() -> clap::App<'static, 'static> {
                     .long("password")
                     .short("p")
                     .takes_value(true),
-            ),
+            )
+            .args(&password_source_args())
+            .arg(keyfile_arg()),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("import")
+            .about("Import passwords from the plain-text json format of export.")
+            .arg(
+                Arg::with_name("vault")
+                    .long("vault")
+                    .short("v")
+                    .takes_value(true),
+            )
+            .arg(Arg::with_name("file").required(true).takes_value(true))
+            .arg(
+                Arg::with_name("password")
+                    .long("password")
+                    .short("p")
+                    .takes_value(true),
+            )
+            .args(&password_source_args())
+            .arg(keyfile_arg()),
     );
 
     app = app.subcommand(
@@ -148,12 +619,22 @@ pub fn build() -> clap::App<'static, 'static> {
                     .short("p")
                     .takes_value(true),
             )
+            .args(&password_source_args())
+            .arg(keyfile_arg())
             .arg(
-                Arg::with_name("verify")
-                    .long("verify")
-                    .short("v")
-                    .takes_value(true),
-            ),
+                Arg::with_name("no-password")
+                    .long("no-password")
+                    .requires("keyfile")
+                    .conflicts_with_all(&[
+                        "password",
+                        "password-stdin",
+                        "password-fd",
+                        "password-file",
+                        "verify",
+                    ])
+                    .help("Unlock the vault with its keyfile alone"),
+            )
+            .arg(Arg::with_name("verify").long("verify").takes_value(true)),
     );
 
     app = app.subcommand(
@@ -172,12 +653,8 @@ pub fn build() -> clap::App<'static, 'static> {
                     .short("p")
                     .takes_value(true),
             )
-            .arg(
-                Arg::with_name("verify")
-                    .long("verify")
-                    .short("v")
-                    .takes_value(true),
-            ),
+            .args(&password_source_args())
+            .arg(keyfile_arg()),
     );
 
     app = app.subcommand(
@@ -196,11 +673,149 @@ pub fn build() -> clap::App<'static, 'static> {
                     .short("p")
                     .takes_value(true),
             )
+            .args(&password_source_args())
+            .arg(keyfile_arg())
             .arg(
                 Arg::with_name("new-password")
                     .long("new-password")
                     .short("n")
                     .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("username")
+                    .long("username")
+                    .short("u")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("url")
+                    .long("url")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(Arg::with_name("notes").long("notes").takes_value(true))
+            .arg(
+                Arg::with_name("tag")
+                    .long("tag")
+                    .short("t")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("field")
+                    .long("field")
+                    .short("f")
+                    .help("Custom field on the form key=value")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("otp")
+                    .long("otp")
+                    .help("TOTP secret in base32 or as an otpauth:// URI")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("generate")
+                    .long("generate")
+                    .short("g")
+                    .help("Generate the new password instead of prompting for it")
+                    .conflicts_with("new-password"),
+            )
+            .args(&generator_args()),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("generate")
+            .about("Generate a random password or passphrase and print it.")
+            .args(&generator_args()),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("passwd")
+            .about("Change the password of a vault.")
+            .arg(
+                Arg::with_name("vault")
+                    .long("vault")
+                    .short("v")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("password")
+                    .long("password")
+                    .short("p")
+                    .takes_value(true),
+            )
+            .args(&password_source_args())
+            .arg(keyfile_arg())
+            .arg(
+                Arg::with_name("new-password")
+                    .long("new-password")
+                    .short("n")
+                    .takes_value(true),
+            )
+            .arg(Arg::with_name("verify").long("verify").takes_value(true))
+            .arg(
+                Arg::with_name("upgrade-kdf")
+                    .long("upgrade-kdf")
+                    .help("Also switch to the key derivation parameters of the configuration"),
+            ),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("agent")
+            .about("Run an agent holding vault keys, used by get, list and add when RPW_AGENT_SOCK is set.")
+            .arg(
+                Arg::with_name("socket")
+                    .long("socket")
+                    .short("s")
+                    .takes_value(true)
+                    .help("Socket to listen on, agent.sock in the rpw directory by default"),
+            )
+            .arg(
+                Arg::with_name("ttl")
+                    .long("ttl")
+                    .short("t")
+                    .takes_value(true)
+                    .help("Seconds to hold each key, agent_ttl of the configuration by default"),
+            )
+            .arg(
+                Arg::with_name("foreground")
+                    .long("foreground")
+                    .short("f")
+                    .help("Stay in the foreground instead of daemonizing"),
+            ),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("doctor")
+            .about("Check the permissions of the rpw directory and its files.")
+            .arg(
+                Arg::with_name("fix")
+                    .long("fix")
+                    .help("Restrict the permissions of the files found"),
+            ),
+    );
+
+    app = app.subcommand(
+        SubCommand::with_name("kdf")
+            .about("Manage the key derivation parameters used for new vaults.")
+            .subcommand(
+                SubCommand::with_name("tune")
+                    .about(
+                        "Benchmark this machine and store parameters hitting a target unlock time.",
+                    )
+                    .arg(
+                        Arg::with_name("target")
+                            .long("target")
+                            .short("t")
+                            .help("Target unlock time in milliseconds")
+                            .default_value("1000")
+                            .takes_value(true),
+                    ),
             ),
     );
 
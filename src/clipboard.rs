@@ -0,0 +1,304 @@
+//! Copying to the clipboard through the tools of the platform, or OSC 52
+//! escape sequences understood by terminals across SSH.
+use crate::keyring::Store;
+use anyhow::{anyhow, Context, Result};
+use openssl::base64::encode_block;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Keyring entry marking the last secret rpw copied, until it is cleared.
+const LAST_COPIED: &str = "rpw:clipboard";
+const KEY_LEN: usize = 32;
+
+/// Where secrets are copied to, a `Backend` outside of tests.
+pub trait Clipboard {
+    /// Replace the contents of the clipboard with `s`.
+    fn copy(&self, s: &str) -> Result<()>;
+    /// The text on the clipboard, `None` when it is empty or holds
+    /// something else.
+    fn paste(&self) -> Result<Option<Zeroizing<String>>>;
+    /// Empty the clipboard.
+    fn clear(&self) -> Result<()>;
+}
+
+/// How rpw copies to the clipboard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// The first of the backends below available in the session.
+    #[default]
+    Auto,
+    Pbcopy,
+    WlCopy,
+    Xclip,
+    Xsel,
+    /// The paste buffer of tmux, also handed to the outer terminal's
+    /// clipboard when tmux is set up to.
+    Tmux,
+    /// An escape sequence asking the terminal to set its clipboard.
+    Osc52,
+}
+
+fn has_var(name: &str) -> bool {
+    std::env::var_os(name).is_some_and(|v| !v.is_empty())
+}
+
+fn installed(cmd: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(cmd).is_file()))
+}
+
+fn tty() -> std::io::Result<File> {
+    OpenOptions::new().write(true).open("/dev/tty")
+}
+
+impl Backend {
+    /// The backend to use, detecting one of the session for `Auto`.
+    pub fn resolve(self) -> Result<Backend> {
+        if self != Backend::Auto {
+            return Ok(self);
+        }
+        let detected = if cfg!(target_os = "macos") {
+            Some(Backend::Pbcopy)
+        } else if has_var("WAYLAND_DISPLAY") && installed("wl-copy") {
+            Some(Backend::WlCopy)
+        } else if has_var("DISPLAY") && installed("xclip") {
+            Some(Backend::Xclip)
+        } else if has_var("DISPLAY") && installed("xsel") {
+            Some(Backend::Xsel)
+        } else if has_var("TMUX") && installed("tmux") {
+            Some(Backend::Tmux)
+        } else if tty().is_ok() {
+            Some(Backend::Osc52)
+        } else {
+            None
+        };
+        detected.ok_or_else(|| {
+            anyhow!("No clipboard found, install wl-copy, xclip or xsel or set clipboard in the configuration")
+        })
+    }
+
+    /// Replace the contents of the clipboard with `s`.
+    pub fn copy(self, s: &str) -> Result<()> {
+        match self.resolve()? {
+            Backend::Auto => unreachable!(),
+            Backend::Pbcopy => pipe("pbcopy", &[], s),
+            Backend::WlCopy => pipe("wl-copy", &[], s),
+            Backend::Xclip => pipe("xclip", &["-selection", "clipboard"], s),
+            Backend::Xsel => pipe("xsel", &["--clipboard", "--input"], s),
+            Backend::Tmux => pipe("tmux", &["load-buffer", "-w", "-"], s),
+            Backend::Osc52 => {
+                let mut tty = tty().context("No terminal to send the clipboard to")?;
+                tty.write_all(osc52(s, has_var("TMUX")).as_bytes())?;
+                tty.flush()?;
+                Ok(())
+            }
+        }
+    }
+
+    /// The text on the clipboard, `None` when it is empty or holds
+    /// something else. OSC 52 gives no way to read it.
+    pub fn paste(self) -> Result<Option<Zeroizing<String>>> {
+        match self.resolve()? {
+            Backend::Auto => unreachable!(),
+            Backend::Pbcopy => read("pbpaste", &[]),
+            Backend::WlCopy => read("wl-paste", &["--no-newline"]),
+            Backend::Xclip => read("xclip", &["-selection", "clipboard", "-o"]),
+            Backend::Xsel => read("xsel", &["--clipboard", "--output"]),
+            Backend::Tmux => read("tmux", &["save-buffer", "-"]),
+            Backend::Osc52 => Err(anyhow!("The clipboard can not be read through OSC 52")),
+        }
+    }
+
+    /// Empty the clipboard.
+    pub fn clear(self) -> Result<()> {
+        match self.resolve()? {
+            Backend::WlCopy => run("wl-copy", &["--clear"]),
+            Backend::Xsel => run("xsel", &["--clipboard", "--delete"]),
+            backend => backend.copy(""),
+        }
+    }
+}
+
+impl Clipboard for Backend {
+    fn copy(&self, s: &str) -> Result<()> {
+        Backend::copy(*self, s)
+    }
+
+    fn paste(&self) -> Result<Option<Zeroizing<String>>> {
+        Backend::paste(*self)
+    }
+
+    fn clear(&self) -> Result<()> {
+        Backend::clear(*self)
+    }
+}
+
+/// HMAC-SHA256 of `s` under `key`, identifying a secret on the clipboard
+/// without keeping it. Unlike a plain digest it can not be cracked without
+/// the key, which is never saved.
+pub fn mac(key: &[u8], s: &str) -> Result<Vec<u8>> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(s.as_bytes())?;
+    Ok(signer.sign_to_vec()?)
+}
+
+/// A secret on the clipboard, along with what was there before it. Handed
+/// to the process clearing the clipboard.
+#[derive(Serialize, Deserialize)]
+pub struct Copied {
+    /// Random key of the MAC of the secret, made for this copy.
+    key: Vec<u8>,
+    mac: Vec<u8>,
+    pub previous: Option<String>,
+}
+
+impl Drop for Copied {
+    fn drop(&mut self) {
+        self.key.zeroize();
+        self.previous.zeroize();
+    }
+}
+
+impl Copied {
+    /// Copy `secret` to `clipboard`, remembering what it replaced. The
+    /// secret is marked in `marks` for `ttl`, so that copying another one
+    /// before it is cleared never remembers it to bring it back. Without
+    /// the mark nothing is remembered.
+    pub fn copy(
+        clipboard: &impl Clipboard,
+        marks: &impl Store,
+        secret: &str,
+        ttl: Duration,
+    ) -> Result<Self> {
+        let previous = clipboard.paste().ok().flatten().filter(|p| {
+            marks
+                .read(LAST_COPIED)
+                .and_then(|m| Copied::from_mark(&m))
+                .is_none_or(|last| !last.holds(p))
+        });
+
+        clipboard.copy(secret)?;
+        let mut key = vec![0; KEY_LEN];
+        openssl::rand::rand_bytes(&mut key)?;
+        let mut copied = Copied {
+            mac: mac(&key, secret)?,
+            key,
+            previous: previous.map(|p| p.to_string()),
+        };
+        // Unmarked secrets, as without a keyring, can not be told from what
+        // the user copied, so nothing is remembered and the clipboard is
+        // emptied instead.
+        if marks
+            .add(LAST_COPIED, &copied.mark(), ttl.max(Duration::from_secs(1)))
+            .is_err()
+        {
+            copied.previous.zeroize();
+        }
+        Ok(copied)
+    }
+
+    /// Restore the clipboard as it was before, unless something else was
+    /// copied since. Returns whether the secret was removed. Clipboards
+    /// which can not be read are cleared regardless.
+    pub fn clear(&self, clipboard: &impl Clipboard, marks: &impl Store) -> Result<bool> {
+        match clipboard.paste() {
+            Ok(Some(current)) if self.holds(&current) => {}
+            Ok(_) => return Ok(false),
+            Err(_) => {}
+        }
+        match &self.previous {
+            Some(previous) => clipboard.copy(previous)?,
+            None => clipboard.clear()?,
+        }
+
+        if marks.read(LAST_COPIED).is_some_and(|m| *m == *self.mark()) {
+            marks.remove(LAST_COPIED)?;
+        }
+        Ok(true)
+    }
+
+    /// Whether `s` is the copied secret.
+    fn holds(&self, s: &str) -> bool {
+        mac(&self.key, s).is_ok_and(|m| m == self.mac)
+    }
+
+    /// The key and MAC, as kept in the keyring.
+    fn mark(&self) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new([&self.key[..], &self.mac[..]].concat())
+    }
+
+    fn from_mark(mark: &[u8]) -> Option<Self> {
+        (mark.len() > KEY_LEN).then(|| Copied {
+            key: mark[..KEY_LEN].to_vec(),
+            mac: mark[KEY_LEN..].to_vec(),
+            previous: None,
+        })
+    }
+}
+
+/// The OSC 52 sequence setting the clipboard to `s`, passed through tmux to
+/// the outer terminal when `tmux` is set.
+pub fn osc52(s: &str, tmux: bool) -> String {
+    let seq = format!("\x1b]52;c;{}\x07", encode_block(s.as_bytes()));
+    if tmux {
+        format!("\x1bPtmux;\x1b{}\x1b\\", seq)
+    } else {
+        seq
+    }
+}
+
+fn spawn(cmd: &str, args: &[&str], stdin: Stdio, stdout: Stdio) -> Result<std::process::Child> {
+    Command::new(cmd)
+        .args(args)
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run {}, is it installed?", cmd))
+}
+
+fn run(cmd: &str, args: &[&str]) -> Result<()> {
+    let status = spawn(cmd, args, Stdio::null(), Stdio::null())?.wait()?;
+    if !status.success() {
+        return Err(anyhow!("{} failed, {}", cmd, status));
+    }
+    Ok(())
+}
+
+/// The output of `cmd`, `None` when it fails or is not text, as the tools
+/// do when the clipboard is empty.
+fn read(cmd: &str, args: &[&str]) -> Result<Option<Zeroizing<String>>> {
+    let mut child = spawn(cmd, args, Stdio::null(), Stdio::piped())?;
+    let mut output = Zeroizing::new(Vec::new());
+    child.stdout.take().unwrap().read_to_end(&mut output)?;
+    if !child.wait()?.success() {
+        return Ok(None);
+    }
+    Ok(std::str::from_utf8(&output)
+        .ok()
+        .map(|s| Zeroizing::new(s.to_string())))
+}
+
+fn pipe(cmd: &str, args: &[&str], s: &str) -> Result<()> {
+    let mut child = spawn(cmd, args, Stdio::piped(), Stdio::null())?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(s.as_bytes())
+        .with_context(|| format!("Failed to write to {}", cmd))?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("{} failed, {}", cmd, status));
+    }
+    Ok(())
+}
@@ -1,41 +1,83 @@
+use crate::clipboard::Backend;
 use crate::files;
-use anyhow::{anyhow, Result};
+use crate::generator::{PassphraseSpec, PasswordSpec};
+use crate::vault::Kdf;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::prelude::*;
+use std::io::ErrorKind;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub clear_copy_timeout: u64,
+    /// How secrets are copied to the clipboard.
+    pub clipboard: Backend,
+    /// Seconds of inactivity after which an open vault is locked, 0 to
+    /// never lock.
+    pub idle_timeout: u64,
+    /// Seconds for which `rpw agent` holds the key of a vault.
+    pub agent_ttl: u64,
+    /// Seconds for which `rpw unlock` caches the key of a vault.
+    pub keyring_ttl: u64,
+    /// Number of backups kept of each vault.
+    pub backups: usize,
+    /// Shell command printing the password of the vault named by
+    /// `RPW_VAULT`, run instead of prompting for it.
+    pub password_command: Option<String>,
+    /// Vault holding the credentials of `rpw git-credential`.
+    pub git_vault: Option<String>,
+    /// Key derivation parameters used for new vaults.
+    pub kdf: Kdf,
+    /// Defaults for generated passwords.
+    pub generator: PasswordSpec,
+    /// Defaults for generated passphrases.
+    pub passphrase: PassphraseSpec,
 }
 
 impl Config {
     pub fn load() -> Result<Self> {
-        let fname = files::rpwd_path("config.json");
-        let f = File::open(&fname).map_err(|_| anyhow!("Failed to open configuration"))?;
+        let fname = files::config_path()?;
+        let f = File::open(&fname)
+            .with_context(|| format!("Failed to open configuration {}", fname.display()))?;
 
         serde_json::from_reader::<File, Config>(f)
-            .map_err(|_| anyhow!("Failed deserializing configuration"))
+            .with_context(|| format!("Failed to parse configuration {}", fname.display()))
     }
 
-    pub fn save(&self) -> Self {
-        let fname = files::rpwd_path("config.json");
-        let json = serde_json::to_string_pretty(&self).expect("Failed to serialize passwords");
+    /// The configuration, written with the defaults when there is none yet.
+    /// One which fails to load is never replaced.
+    pub fn load_or_create() -> Result<Self> {
+        match std::fs::symlink_metadata(files::config_path()?) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Config::new().save(),
+            _ => Config::load(),
+        }
+    }
+
+    pub fn save(&self) -> Result<Self> {
+        let fname = files::config_path()?;
+        let json =
+            serde_json::to_string_pretty(&self).context("Failed to serialize configuration")?;
 
-        std::fs::create_dir_all(&files::rpwd()).expect("Failed to create rpw dir");
-        File::create(&fname)
-            .map(|mut f| {
-                f.write_all(json.as_bytes()).expect("Failed to write file");
-            })
-            .map_err(|_| format!("Failed to create database {}", fname.display()))
-            .expect("Failed to create vault file");
+        files::create_private_dir(&files::config_dir()?)?;
+        files::write_atomic(&fname, json.as_bytes()).context("Failed to write configuration")?;
 
-        self.clone()
+        Ok(self.clone())
     }
 
     pub fn new() -> Self {
         Config {
             clear_copy_timeout: 5,
+            clipboard: Backend::default(),
+            idle_timeout: 300,
+            agent_ttl: 900,
+            keyring_ttl: 900,
+            backups: 5,
+            password_command: None,
+            git_vault: None,
+            kdf: Kdf::default(),
+            generator: PasswordSpec::default(),
+            passphrase: PassphraseSpec::default(),
         }
     }
 }
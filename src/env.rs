@@ -0,0 +1,86 @@
+//! Environment variables set to secrets of a vault by `rpw run`.
+use crate::error::Kind;
+use crate::vault::Entry;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// An environment variable and the secret it is set to, `ALIAS` for the
+/// password of an entry or `ALIAS/FIELD` for one of its fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub var: String,
+    pub secret: String,
+}
+
+impl FromStr for Mapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (var, secret) = s
+            .split_once('=')
+            .ok_or_else(|| Kind::Usage.error(format!("'{}' is not of the form VAR=ALIAS", s)))?;
+        let (var, secret) = (var.trim(), secret.trim());
+
+        let valid = var
+            .chars()
+            .enumerate()
+            .all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+        if var.is_empty() || !valid {
+            return Err(Kind::Usage.error(format!(
+                "'{}' is not a valid environment variable name",
+                var
+            )));
+        }
+        if secret.is_empty() {
+            return Err(Kind::Usage.error(format!("No entry given for {}", var)));
+        }
+        Ok(Mapping {
+            var: var.to_string(),
+            secret: secret.to_string(),
+        })
+    }
+}
+
+/// Mappings of `text`, one `VAR=ALIAS[/FIELD]` per line. Blank lines and
+/// lines starting with `#` are skipped.
+pub fn parse(text: &str) -> Result<Vec<Mapping>> {
+    text.lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim()))
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+        .map(|(n, l)| l.parse().with_context(|| format!("Line {}", n)))
+        .collect()
+}
+
+pub fn parse_file(path: &Path) -> Result<Vec<Mapping>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read mapping file {}", path.display()))?;
+    parse(&text).with_context(|| format!("Invalid mapping file {}", path.display()))
+}
+
+/// The value of `secret`, looking entries up with `find`. An alias which
+/// itself contains `/` is matched whole before being split into a field.
+pub fn resolve<F>(secret: &str, mut find: F) -> Result<Zeroizing<String>>
+where
+    F: FnMut(&str) -> Result<Option<Entry>>,
+{
+    let (alias, field, entry) = match find(secret)? {
+        Some(entry) => (secret, "password", entry),
+        None => {
+            let (alias, field) = secret.rsplit_once('/').ok_or_else(|| {
+                Kind::NotFound.error(format!("No entry '{}' in the vault", secret))
+            })?;
+            let entry = find(alias)?.ok_or_else(|| {
+                Kind::NotFound.error(format!("No entry '{}' in the vault", alias))
+            })?;
+            (alias, field, entry)
+        }
+    };
+
+    let value = entry.field(field).ok_or_else(|| {
+        Kind::NotFound.error(format!("Entry '{}' has no field '{}'", alias, field))
+    })?;
+    Ok(Zeroizing::new(value.to_string()))
+}
@@ -0,0 +1,67 @@
+use serde::Serialize;
+use std::fmt;
+
+/// Class of an error, which decides the exit code of rpw so that scripts
+/// can tell failures apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Failure,
+    /// Invalid or missing arguments.
+    Usage,
+    /// No such vault, entry, field or backup.
+    NotFound,
+    /// Wrong password or keyfile, or a tampered vault.
+    Auth,
+    /// The vault was changed by another process.
+    Conflict,
+    /// A confirmation was answered with no.
+    Declined,
+    /// Input was needed while prompting is disabled.
+    InputRequired,
+    /// Files of rpw are owned by another user.
+    Permission,
+}
+
+impl Kind {
+    pub fn code(self) -> i32 {
+        match self {
+            Kind::Failure => 1,
+            Kind::Usage => 2,
+            Kind::NotFound => 3,
+            Kind::Auth => 4,
+            Kind::Conflict => 5,
+            Kind::Declined => 6,
+            Kind::InputRequired => 7,
+            Kind::Permission => 8,
+        }
+    }
+
+    pub fn error(self, msg: impl fmt::Display) -> anyhow::Error {
+        anyhow::Error::new(Error {
+            kind: self,
+            message: msg.to_string(),
+        })
+    }
+
+    /// Class of `e`, found anywhere in its chain of causes.
+    pub fn of(e: &anyhow::Error) -> Kind {
+        e.chain()
+            .find_map(|c| c.downcast_ref::<Error>())
+            .map_or(Kind::Failure, |e| e.kind)
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub kind: Kind,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
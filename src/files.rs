@@ -1,19 +1,272 @@
-use anyhow::Result;
-use std::path::PathBuf;
+use anyhow::{anyhow, Context, Result};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 
-const RPW_DIR: &str = ".rpw.d";
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
 
-pub fn rpwd() -> PathBuf {
-    dirs::home_dir().unwrap().join(RPW_DIR)
+/// Environment variable overriding the directory of all rpw files.
+pub const HOME_VAR: &str = "RPW_HOME";
+const APP_DIR: &str = "rpw";
+/// Directory used by earlier versions of rpw.
+const LEGACY_DIR: &str = ".rpw.d";
+const CONFIG_FILE: &str = "config.json";
+const DIR_MODE: u32 = 0o700;
+const FILE_MODE: u32 = 0o600;
+
+fn home_override() -> Option<PathBuf> {
+    std::env::var_os(HOME_VAR)
+        .filter(|h| !h.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Directory of the vaults, `$RPW_HOME` or else the XDG data directory.
+pub fn rpwd() -> Result<PathBuf> {
+    match home_override() {
+        Some(home) => Ok(home),
+        None => dirs::data_dir()
+            .map(|d| d.join(APP_DIR))
+            .with_context(|| format!("No data directory found, please set {}", HOME_VAR)),
+    }
 }
 
-pub fn rpwd_path(name: &str) -> PathBuf {
-    rpwd().join(name.to_string())
+/// Directory of the configuration, `$RPW_HOME` or else the XDG config
+/// directory.
+pub fn config_dir() -> Result<PathBuf> {
+    match home_override() {
+        Some(home) => Ok(home),
+        None => dirs::config_dir()
+            .map(|d| d.join(APP_DIR))
+            .with_context(|| format!("No config directory found, please set {}", HOME_VAR)),
+    }
+}
+
+pub fn config_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join(CONFIG_FILE))
+}
+
+pub fn rpwd_path(name: &str) -> Result<PathBuf> {
+    Ok(rpwd()?.join(name))
+}
+
+pub fn backups_dir() -> Result<PathBuf> {
+    Ok(rpwd()?.join("backups"))
 }
 
 pub fn delete(name: &str) -> Result<()> {
-    let rpw_d = dirs::home_dir().unwrap().join(RPW_DIR);
-    let fname = rpw_d.join(&name);
-    std::fs::remove_file(&fname)?;
+    std::fs::remove_file(rpwd_path(name)?)?;
+    Ok(())
+}
+
+/// What `migrate_legacy` did with the directory of earlier versions.
+#[derive(Debug, PartialEq)]
+pub enum Migration {
+    /// Its files were moved to the XDG directories.
+    Moved(PathBuf),
+    /// It was left alone, as the XDG directories are already in use.
+    Skipped(PathBuf),
+}
+
+/// Move the files of `$HOME/.rpw.d` to the XDG directories, unless these
+/// already exist. `None` when there is no old directory.
+pub fn migrate_legacy() -> Result<Option<Migration>> {
+    let legacy = match dirs::home_dir() {
+        Some(home) if home_override().is_none() => home.join(LEGACY_DIR),
+        _ => return Ok(None),
+    };
+    if !legacy.is_dir() {
+        return Ok(None);
+    }
+    if rpwd()?.exists() || config_dir()?.exists() {
+        return Ok(Some(Migration::Skipped(legacy)));
+    }
+
+    create_private_dir(&rpwd()?)?;
+    create_private_dir(&config_dir()?)?;
+    for entry in std::fs::read_dir(&legacy)? {
+        let src = entry?.path();
+        let dest = if src.file_name() == Some(CONFIG_FILE.as_ref()) {
+            config_path()?
+        } else {
+            rpwd()?.join(src.file_name().unwrap())
+        };
+        std::fs::rename(&src, &dest)
+            .with_context(|| format!("Failed to move {} to {}", src.display(), dest.display()))?;
+    }
+    std::fs::remove_dir(&legacy)
+        .with_context(|| format!("Failed to remove {}", legacy.display()))?;
+    Ok(Some(Migration::Moved(legacy)))
+}
+
+/// Create `path` and any missing parents, accessible only by the user.
+pub fn create_private_dir(path: &Path) -> Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(DIR_MODE);
+    builder
+        .create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))
+}
+
+/// Create or truncate `path`, readable and writable only by the user.
+pub fn create_private(path: &Path) -> Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(FILE_MODE);
+
+    let f = options.open(path)?;
+    // The mode only applies to new files.
+    #[cfg(unix)]
+    f.set_permissions(std::fs::Permissions::from_mode(FILE_MODE))?;
+    Ok(f)
+}
+
+/// Replace the contents of `path` without ever leaving it half written, by
+/// writing and syncing a temporary sibling and renaming it over the original.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let fname = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.tmp", fname));
+
+    let mut f = create_private(&tmp)?;
+    f.write_all(data)?;
+    f.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+
+    // Persist the rename itself.
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        File::open(dir)?.sync_all()?;
+    }
     Ok(())
 }
+
+/// An advisory lock on a file, released when dropped.
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Block until the exclusive lock on `path` is acquired, creating the
+    /// file if needed.
+    pub fn exclusive(path: &Path) -> Result<Self> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(false);
+        #[cfg(unix)]
+        options.mode(FILE_MODE);
+        let file = options
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("Failed to lock {}", path.display()));
+            }
+        }
+        Ok(FileLock { _file: file })
+    }
+}
+
+/// A file or directory of rpw which others may be able to access.
+#[derive(Debug)]
+pub struct PermissionIssue {
+    pub path: PathBuf,
+    pub mode: u32,
+    pub expected: u32,
+    /// Owned by another user, which rpw can not fix.
+    pub foreign_owner: bool,
+}
+
+impl PermissionIssue {
+    pub fn fix(&self) -> Result<()> {
+        // Changing the mode of a symlink changes that of its target, which
+        // may be anywhere.
+        if std::fs::symlink_metadata(&self.path)?
+            .file_type()
+            .is_symlink()
+        {
+            return Err(anyhow!("{} is a symlink", self.path.display()));
+        }
+        #[cfg(unix)]
+        std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(self.expected))
+            .with_context(|| format!("Failed to change mode of {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for PermissionIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.foreign_owner {
+            write!(f, "{} is owned by another user", self.path.display())
+        } else {
+            write!(
+                f,
+                "{} has mode {:o}, expected {:o}",
+                self.path.display(),
+                self.mode,
+                self.expected
+            )
+        }
+    }
+}
+
+#[cfg(unix)]
+fn check_path(path: &Path, issues: &mut Vec<PermissionIssue>) -> Result<()> {
+    let meta = std::fs::symlink_metadata(path)?;
+    // The mode of symlinks is meaningless, and what they point to is not
+    // rpw's to check.
+    if meta.file_type().is_symlink() {
+        return Ok(());
+    }
+    let expected = if meta.is_dir() { DIR_MODE } else { FILE_MODE };
+    let mode = meta.mode() & 0o777;
+    let foreign_owner = meta.uid() != unsafe { libc::geteuid() };
+
+    if foreign_owner || mode & 0o077 != 0 {
+        issues.push(PermissionIssue {
+            path: path.to_path_buf(),
+            mode,
+            expected,
+            foreign_owner,
+        });
+    }
+
+    if meta.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            check_path(&entry?.path(), issues)?;
+        }
+    }
+    Ok(())
+}
+
+/// Files and directories below the rpw directories with loose permissions or
+/// foreign ownership.
+pub fn permission_issues() -> Result<Vec<PermissionIssue>> {
+    let mut issues = Vec::new();
+    #[cfg(unix)]
+    {
+        // The directories themselves may be symlinks, e.g. into a synced
+        // folder, so are checked where they lead.
+        let mut dirs = Vec::new();
+        for dir in [rpwd()?, config_dir()?] {
+            if dir.exists() {
+                dirs.push(dir.canonicalize()?);
+            }
+        }
+        dirs.dedup();
+        for dir in &dirs {
+            check_path(dir, &mut issues)?;
+        }
+    }
+    Ok(issues)
+}
@@ -0,0 +1,245 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::path::PathBuf;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!#$%&()*+,-./:;<=>?@[]^_{}~";
+const AMBIGUOUS: &str = "0O1lI|";
+/// EFF large wordlist (CC BY 3.0 US), 7776 words for five dice.
+const EFF_LONG: &str = include_str!("wordlists/eff_large_wordlist.txt");
+/// EFF short wordlist 1 (CC BY 3.0 US), 1296 shorter words for four dice.
+const EFF_SHORT: &str = include_str!("wordlists/eff_short_wordlist_1.txt");
+
+/// Rules for generating random character passwords.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordSpec {
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    /// Leave out characters which are easily confused, such as 0 and O.
+    pub exclude_ambiguous: bool,
+    pub min_lowercase: usize,
+    pub min_uppercase: usize,
+    pub min_digits: usize,
+    pub min_symbols: usize,
+}
+
+impl Default for PasswordSpec {
+    fn default() -> Self {
+        PasswordSpec {
+            length: 24,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            exclude_ambiguous: false,
+            min_lowercase: 1,
+            min_uppercase: 1,
+            min_digits: 1,
+            min_symbols: 1,
+        }
+    }
+}
+
+/// Words passphrases are made of, `long` or `short` for the bundled EFF
+/// lists or else the path of a list.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub enum Wordlist {
+    #[default]
+    Long,
+    Short,
+    /// File with one word per line, leading dice numbers are ignored.
+    Path(PathBuf),
+}
+
+impl From<&str> for Wordlist {
+    fn from(s: &str) -> Self {
+        match s {
+            "long" => Wordlist::Long,
+            "short" => Wordlist::Short,
+            path => Wordlist::Path(PathBuf::from(path)),
+        }
+    }
+}
+
+impl fmt::Display for Wordlist {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Wordlist::Long => write!(f, "long"),
+            Wordlist::Short => write!(f, "short"),
+            Wordlist::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Serialize for Wordlist {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Wordlist {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        Ok(Wordlist::from(s.as_str()))
+    }
+}
+
+impl Wordlist {
+    /// The distinct words of the list.
+    pub fn words(&self) -> Result<Vec<String>> {
+        let text = match self {
+            Wordlist::Long => EFF_LONG.to_string(),
+            Wordlist::Short => EFF_SHORT.to_string(),
+            Wordlist::Path(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read wordlist {}", path.display()))?,
+        };
+
+        let mut words: Vec<String> = text
+            .lines()
+            .filter_map(|l| l.split_whitespace().last())
+            .map(String::from)
+            .collect();
+        words.sort();
+        words.dedup();
+
+        if words.len() < 2 {
+            return Err(anyhow!("Wordlist needs at least two distinct words"));
+        }
+        Ok(words)
+    }
+}
+
+/// Rules for generating diceware style passphrases.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PassphraseSpec {
+    pub words: usize,
+    pub separator: String,
+    pub capitalize: bool,
+    /// Append a random digit to one of the words.
+    pub digit: bool,
+    pub wordlist: Wordlist,
+}
+
+impl Default for PassphraseSpec {
+    fn default() -> Self {
+        PassphraseSpec {
+            words: 7,
+            separator: String::from("-"),
+            capitalize: false,
+            digit: false,
+            wordlist: Wordlist::default(),
+        }
+    }
+}
+
+/// Uniformly random number in `0..n`.
+pub(crate) fn rand_below(n: usize) -> usize {
+    let n = n as u64;
+    let zone = u64::MAX - u64::MAX % n;
+    loop {
+        let mut buf = [0; 8];
+        openssl::rand::rand_bytes(&mut buf).unwrap();
+        let r = u64::from_le_bytes(buf);
+        if r < zone {
+            return (r % n) as usize;
+        }
+    }
+}
+
+fn shuffle<T>(v: &mut [T]) {
+    for i in (1..v.len()).rev() {
+        v.swap(i, rand_below(i + 1));
+    }
+}
+
+impl PasswordSpec {
+    fn classes(&self) -> Vec<(Vec<char>, usize)> {
+        [
+            (self.lowercase, LOWERCASE, self.min_lowercase),
+            (self.uppercase, UPPERCASE, self.min_uppercase),
+            (self.digits, DIGITS, self.min_digits),
+            (self.symbols, SYMBOLS, self.min_symbols),
+        ]
+        .iter()
+        .filter(|(enabled, _, _)| *enabled)
+        .map(|(_, chars, min)| {
+            let chars = chars
+                .chars()
+                .filter(|c| !self.exclude_ambiguous || !AMBIGUOUS.contains(*c))
+                .collect();
+            (chars, *min)
+        })
+        .collect()
+    }
+
+    pub fn generate(&self) -> Result<String> {
+        let classes = self.classes();
+        if classes.is_empty() {
+            return Err(anyhow!("No character classes enabled"));
+        }
+
+        let required: usize = classes.iter().map(|(_, min)| min).sum();
+        if required > self.length {
+            return Err(anyhow!(
+                "Length {} is shorter than the {} required characters",
+                self.length,
+                required
+            ));
+        }
+
+        let all: Vec<char> = classes
+            .iter()
+            .flat_map(|(c, _)| c.iter().copied())
+            .collect();
+        let mut pw: Vec<char> = Vec::with_capacity(self.length);
+        for (chars, min) in &classes {
+            pw.extend((0..*min).map(|_| chars[rand_below(chars.len())]));
+        }
+        while pw.len() < self.length {
+            pw.push(all[rand_below(all.len())]);
+        }
+        shuffle(&mut pw);
+
+        Ok(pw.into_iter().collect())
+    }
+}
+
+impl PassphraseSpec {
+    /// Generate a passphrase together with its entropy in bits.
+    pub fn generate(&self) -> Result<(String, f64)> {
+        if self.words == 0 {
+            return Err(anyhow!("Passphrase needs at least one word"));
+        }
+
+        let list = self.wordlist.words()?;
+        let mut words: Vec<String> = (0..self.words)
+            .map(|_| list[rand_below(list.len())].clone())
+            .collect();
+        let mut entropy = self.words as f64 * (list.len() as f64).log2();
+
+        if self.capitalize {
+            for w in words.iter_mut() {
+                let mut chars = w.chars();
+                *w = chars
+                    .next()
+                    .map(|c| c.to_uppercase().chain(chars).collect())
+                    .unwrap_or_default();
+            }
+        }
+        if self.digit {
+            let i = rand_below(words.len());
+            words[i].push_str(&rand_below(10).to_string());
+            entropy += (10.0 * words.len() as f64).log2();
+        }
+
+        Ok((words.join(&self.separator), entropy))
+    }
+}
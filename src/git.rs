@@ -0,0 +1,264 @@
+//! The protocol of git credential helpers, letting `rpw git-credential`
+//! answer git with entries of a vault.
+use crate::error::Kind;
+use crate::vault::Entry;
+use anyhow::Result;
+use std::io::{BufRead, Write};
+use zeroize::Zeroize;
+
+/// Tag of the entries stored by git, the only ones it may erase.
+pub const TAG: &str = "git";
+
+/// The vault git's credentials are looked up in and stored to.
+pub trait Store {
+    /// All entries, which may come without their secrets.
+    fn entries(&mut self) -> Result<Vec<(String, Entry)>>;
+    fn entry(&mut self, alias: &str) -> Result<Option<Entry>>;
+    /// Store `entry` as `alias`, removing `alias` when `None`.
+    fn update(&mut self, alias: String, entry: Option<Entry>) -> Result<()>;
+}
+
+/// The attributes of a credential exchanged with git, see
+/// git-credential(1).
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Credential {
+    pub protocol: Option<String>,
+    pub host: Option<String>,
+    pub path: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Drop for Credential {
+    fn drop(&mut self) {
+        self.password.zeroize();
+    }
+}
+
+/// A URL split into the parts git matches credentials by.
+struct Url<'a> {
+    protocol: Option<&'a str>,
+    host: &'a str,
+    path: Option<&'a str>,
+}
+
+impl<'a> Url<'a> {
+    fn parse(url: &'a str) -> Self {
+        let (protocol, rest) = match url.split_once("://") {
+            Some((p, rest)) => (Some(p), rest),
+            None => (None, url),
+        };
+        let (host, path) = match rest.split_once('/') {
+            Some((host, path)) => (host, Some(path.trim_matches('/'))),
+            None => (rest, None),
+        };
+        // Userinfo is not part of the host git sends.
+        let host = host.rsplit_once('@').map_or(host, |(_, h)| h);
+        Url {
+            protocol,
+            host,
+            path: path.filter(|p| !p.is_empty()),
+        }
+    }
+}
+
+impl Credential {
+    /// Read the `key=value` lines git writes, up to an empty line.
+    pub fn read(reader: impl BufRead) -> Result<Self> {
+        let mut cred = Credential::default();
+        for line in reader.lines() {
+            let mut line = line?;
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                Kind::Usage.error(format!("'{}' is not of the form key=value", line))
+            })?;
+            let value = Some(value.to_string());
+            match key {
+                "protocol" => cred.protocol = value,
+                "host" => cred.host = value,
+                "path" => cred.path = value,
+                "username" => cred.username = value,
+                "password" => cred.password = value,
+                "url" => {
+                    let url = Url::parse(value.as_deref().unwrap());
+                    cred.protocol = url.protocol.map(String::from);
+                    cred.host = Some(url.host.to_string());
+                    cred.path = url.path.map(String::from);
+                }
+                // Attributes rpw does not store.
+                _ => {}
+            }
+            line.zeroize();
+        }
+        Ok(cred)
+    }
+
+    /// Answer a `get` with the username and password of `entry`.
+    pub fn write_entry(&self, entry: &Entry, mut w: impl Write) -> Result<()> {
+        if let Some(username) = entry.username.as_ref().or(self.username.as_ref()) {
+            writeln!(w, "username={}", username)?;
+        }
+        writeln!(w, "password={}", entry.password)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// The URL of the credential, `protocol://host[/path]`, `None` when git
+    /// did not say where the credential is for.
+    pub fn url(&self) -> Option<String> {
+        let protocol = self.protocol.as_ref()?;
+        let host = self.host.as_ref()?;
+        Some(match self.path() {
+            Some(path) => format!("{}://{}/{}", protocol, host, path),
+            None => format!("{}://{}", protocol, host),
+        })
+    }
+
+    /// Alias of the entry git stores the credential in.
+    pub fn alias(&self) -> Option<String> {
+        let host = self.host.as_ref()?;
+        Some(match self.path() {
+            Some(path) => format!("git/{}/{}", host, path),
+            None => format!("git/{}", host),
+        })
+    }
+
+    fn path(&self) -> Option<&str> {
+        self.path
+            .as_deref()
+            .map(|p| p.trim_matches('/'))
+            .filter(|p| !p.is_empty())
+    }
+
+    /// How well `entry` matches, `None` if it does not. An entry with a URL
+    /// of the repository outranks one with a URL of the host only, which
+    /// matches every repository on the host, and that one an entry of some
+    /// repository when git did not say which.
+    pub fn score(&self, entry: &Entry) -> Option<u8> {
+        let protocol = self.protocol.as_deref()?;
+        let host = self.host.as_deref()?;
+        if let (Some(wanted), Some(username)) = (&self.username, &entry.username) {
+            if wanted != username {
+                return None;
+            }
+        }
+
+        let mut best = None;
+        for url in &entry.urls {
+            let url = Url::parse(url);
+            if url
+                .protocol
+                .is_some_and(|p| !p.eq_ignore_ascii_case(protocol))
+                || !url.host.eq_ignore_ascii_case(host)
+            {
+                continue;
+            }
+            // Without credential.useHttpPath git sends no path, which an
+            // entry of a repository on the host may still be right for.
+            let score = match (url.path, self.path()) {
+                (None, _) => 1,
+                (Some(path), Some(wanted)) if path == wanted => 2,
+                (Some(_), None) => 0,
+                (Some(_), Some(_)) => continue,
+            };
+            best = best.max(Some(score));
+        }
+        best
+    }
+
+    /// Alias of the entry best matching the credential. Ties go to entries
+    /// stored by git, as these were the last to work, and then by alias.
+    pub fn find<'a, I>(&self, entries: I) -> Option<&'a str>
+    where
+        I: IntoIterator<Item = (&'a String, &'a Entry)>,
+    {
+        entries
+            .into_iter()
+            .filter_map(|(alias, e)| {
+                let score = self.score(e)?;
+                Some(((score, e.tags.iter().any(|t| t == TAG)), alias))
+            })
+            .min_by(|(a, x), (b, y)| b.cmp(a).then(x.cmp(y)))
+            .map(|(_, alias)| alias.as_str())
+    }
+
+    /// Answer a `get` with the best matching entry of `store`, returning
+    /// its alias.
+    pub fn get(&self, store: &mut impl Store, w: impl Write) -> Result<Option<String>> {
+        if self.url().is_none() {
+            return Ok(None);
+        }
+        let entries = store.entries()?;
+        let alias = match self.find(entries.iter().map(|(a, e)| (a, e))) {
+            Some(alias) => alias.to_string(),
+            None => return Ok(None),
+        };
+        match store.entry(&alias)? {
+            Some(entry) => {
+                self.write_entry(&entry, w)?;
+                Ok(Some(alias))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Store the credential git reports working, unless the best matching
+    /// entry already holds it, returning the alias it was stored as.
+    pub fn store(&self, store: &mut impl Store) -> Result<Option<String>> {
+        let (url, password) = match (self.url(), &self.password) {
+            (Some(url), Some(password)) => (url, password),
+            _ => return Ok(None),
+        };
+        let entries = store.entries()?;
+        if let Some(alias) = self.find(entries.iter().map(|(a, e)| (a, e))) {
+            let unchanged = store.entry(alias)?.is_some_and(|e| {
+                &e.password == password && (self.username.is_none() || e.username == self.username)
+            });
+            if unchanged {
+                return Ok(None);
+            }
+        }
+
+        let alias = self.alias().unwrap();
+        let mut entry = store
+            .entry(&alias)?
+            .unwrap_or_else(|| Entry::new(String::new()));
+        entry.password.zeroize();
+        entry.password.push_str(password);
+        entry.username = self.username.clone();
+        entry.urls = vec![url];
+        if !entry.tags.iter().any(|t| t == TAG) {
+            entry.tags.push(TAG.to_string());
+        }
+        entry.touch();
+        store.update(alias.clone(), Some(entry))?;
+        Ok(Some(alias))
+    }
+
+    /// Remove the matching entries stored by git, only those with the
+    /// password git rejected when it gives one, returning their aliases.
+    pub fn erase(&self, store: &mut impl Store) -> Result<Vec<String>> {
+        let mut erased = Vec::new();
+        if self.url().is_none() {
+            return Ok(erased);
+        }
+        for (alias, e) in store.entries()? {
+            if !e.tags.iter().any(|t| t == TAG) || self.score(&e).is_none() {
+                continue;
+            }
+            // Listed by the agent without their passwords.
+            let matches = match (&self.password, store.entry(&alias)?) {
+                (Some(p), Some(e)) => &e.password == p,
+                (None, entry) => entry.is_some(),
+                _ => false,
+            };
+            if matches {
+                store.update(alias.clone(), None)?;
+                erased.push(alias);
+            }
+        }
+        Ok(erased)
+    }
+}
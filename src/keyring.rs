@@ -0,0 +1,218 @@
+//! Caching of derived vault keys in the session keyring of the Linux kernel,
+//! letting consecutive commands skip both the password prompt and the KDF.
+use crate::error::Kind;
+use crate::files;
+use crate::secret::SecretBytes;
+use anyhow::Result;
+use std::time::Duration;
+
+/// Where keys are cached, the kernel keyring outside of tests. Entries are
+/// found by their description.
+pub trait Store {
+    /// Add `payload` as entry `desc`, replacing any earlier one, to be
+    /// removed by the store after `ttl`.
+    fn add(&self, desc: &str, payload: &[u8], ttl: Duration) -> Result<()>;
+    fn read(&self, desc: &str) -> Option<SecretBytes>;
+    /// Remove entry `desc`, returning whether there was one.
+    fn remove(&self, desc: &str) -> Result<bool>;
+}
+
+/// The session keyring of the Linux kernel.
+pub struct Kernel;
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::*;
+    use anyhow::{anyhow, Context};
+    use libc::{c_long, syscall, SYS_add_key, SYS_keyctl, KEY_SPEC_SESSION_KEYRING};
+    use std::ffi::CString;
+    use std::ptr;
+
+    const KEY_TYPE: &[u8] = b"user\0";
+
+    fn find(desc: &CString) -> Option<c_long> {
+        let id = unsafe {
+            syscall(
+                SYS_keyctl,
+                libc::KEYCTL_SEARCH as c_long,
+                KEY_SPEC_SESSION_KEYRING as c_long,
+                KEY_TYPE.as_ptr(),
+                desc.as_ptr(),
+                0 as c_long,
+            )
+        };
+        (id >= 0).then_some(id)
+    }
+
+    /// The session keyring, or the user session keyring when the process is
+    /// outside of a login session. Adding to `KEY_SPEC_SESSION_KEYRING`
+    /// itself would then create a keyring which vanishes with the process.
+    fn session_keyring() -> Result<c_long> {
+        let id = unsafe {
+            syscall(
+                SYS_keyctl,
+                libc::KEYCTL_GET_KEYRING_ID as c_long,
+                KEY_SPEC_SESSION_KEYRING as c_long,
+                0 as c_long,
+            )
+        };
+        if id < 0 {
+            return Err(std::io::Error::last_os_error()).context("No session keyring found");
+        }
+        Ok(id)
+    }
+
+    impl Store for Kernel {
+        fn add(&self, desc: &str, payload: &[u8], ttl: Duration) -> Result<()> {
+            let desc = CString::new(desc).context("Invalid vault name")?;
+            let keyring = session_keyring()?;
+            let id = unsafe {
+                syscall(
+                    SYS_add_key,
+                    KEY_TYPE.as_ptr(),
+                    desc.as_ptr(),
+                    payload.as_ptr(),
+                    payload.len(),
+                    keyring,
+                )
+            };
+            if id < 0 {
+                return Err(std::io::Error::last_os_error())
+                    .context("Failed to add the key to the session keyring");
+            }
+            let ret = unsafe {
+                syscall(
+                    SYS_keyctl,
+                    libc::KEYCTL_SET_TIMEOUT as c_long,
+                    id,
+                    ttl.as_secs() as c_long,
+                )
+            };
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                unsafe { syscall(SYS_keyctl, libc::KEYCTL_INVALIDATE as c_long, id) };
+                return Err(anyhow!(
+                    "Failed to set the timeout of the cached key: {}",
+                    err
+                ));
+            }
+            Ok(())
+        }
+
+        fn read(&self, desc: &str) -> Option<SecretBytes> {
+            let id = find(&CString::new(desc).ok()?)?;
+            let read = |buf: *mut u8, len: usize| unsafe {
+                syscall(SYS_keyctl, libc::KEYCTL_READ as c_long, id, buf, len)
+            };
+
+            let len = read(ptr::null_mut(), 0);
+            if len <= 0 {
+                return None;
+            }
+            let mut key = SecretBytes::new(vec![0; len as usize]);
+            if read(key.as_mut_ptr(), key.len()) != len {
+                return None;
+            }
+            Some(key)
+        }
+
+        fn remove(&self, desc: &str) -> Result<bool> {
+            let id = match find(&CString::new(desc).context("Invalid vault name")?) {
+                Some(id) => id,
+                None => return Ok(false),
+            };
+            let ret = unsafe { syscall(SYS_keyctl, libc::KEYCTL_INVALIDATE as c_long, id) };
+            if ret < 0 {
+                return Err(std::io::Error::last_os_error())
+                    .context("Failed to remove the cached key");
+            }
+            Ok(true)
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::*;
+    use anyhow::anyhow;
+
+    impl Store for Kernel {
+        fn add(&self, _desc: &str, _payload: &[u8], _ttl: Duration) -> Result<()> {
+            Err(anyhow!("Caching keys requires the Linux kernel keyring"))
+        }
+
+        fn read(&self, _desc: &str) -> Option<SecretBytes> {
+            None
+        }
+
+        fn remove(&self, _desc: &str) -> Result<bool> {
+            Ok(false)
+        }
+    }
+}
+
+/// A cached vault key, along with the digest of the keyfile it was derived
+/// with for vaults which use one.
+pub struct Cached {
+    pub key: SecretBytes,
+    pub keyfile: Option<SecretBytes>,
+}
+
+/// Description of the key of `vault`, distinct for every rpw directory.
+fn description(vault: &str) -> Result<String> {
+    Ok(format!("rpw:{}", files::rpwd_path(vault)?.display()))
+}
+
+fn keyfile_description(vault: &str) -> Result<String> {
+    Ok(format!("{}:keyfile", description(vault)?))
+}
+
+/// Cache the derived key of `vault` and the digest of its keyfile in
+/// `store` for `ttl`, which can not be zero as the kernel would then keep
+/// them for good.
+pub fn store_in(store: &impl Store, vault: &str, cached: &Cached, ttl: Duration) -> Result<()> {
+    if ttl.as_secs() == 0 {
+        return Err(Kind::Usage.error("Keys must be cached for at least a second"));
+    }
+    // The keyfile goes first, so that a key is never found along with the
+    // keyfile of an earlier one.
+    let added = match &cached.keyfile {
+        Some(keyfile) => store.add(&keyfile_description(vault)?, keyfile, ttl),
+        None => store.remove(&keyfile_description(vault)?).map(|_| ()),
+    }
+    .and_then(|_| store.add(&description(vault)?, &cached.key, ttl));
+    if added.is_err() {
+        let _ = forget_in(store, vault);
+    }
+    added
+}
+
+/// The cached key of `vault` in `store`, if any.
+pub fn load_from(store: &impl Store, vault: &str) -> Option<Cached> {
+    let key = store.read(&description(vault).ok()?)?;
+    let keyfile = store.read(&keyfile_description(vault).ok()?);
+    Some(Cached { key, keyfile })
+}
+
+/// Remove the cached key of `vault` from `store`, returning whether there
+/// was one.
+pub fn forget_in(store: &impl Store, vault: &str) -> Result<bool> {
+    let forgotten = store.remove(&description(vault)?)?;
+    store.remove(&keyfile_description(vault)?)?;
+    Ok(forgotten)
+}
+
+/// Cache the derived key of `vault` for `ttl`.
+pub fn store(vault: &str, cached: &Cached, ttl: Duration) -> Result<()> {
+    store_in(&Kernel, vault, cached, ttl)
+}
+
+/// The cached key of `vault`, if any.
+pub fn load(vault: &str) -> Option<Cached> {
+    load_from(&Kernel, vault)
+}
+
+/// Remove the cached key of `vault`, returning whether there was one.
+pub fn forget(vault: &str) -> Result<bool> {
+    forget_in(&Kernel, vault)
+}
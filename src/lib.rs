@@ -1,4 +1,16 @@
+pub mod agent;
 pub mod cli;
+pub mod clipboard;
 pub mod config;
+pub mod env;
+pub mod error;
 pub mod files;
+pub mod generator;
+pub mod git;
+pub mod keyring;
+pub mod otp;
+pub mod output;
+pub mod secret;
+pub mod session;
+pub mod template;
 pub mod vault;
@@ -1,284 +1,1360 @@
+use agent::{Request, Response};
 use anyhow::{anyhow, Context, Result};
 use clap::{value_t, ArgMatches};
+use clipboard::Copied;
 use config::Config;
+use error::Kind;
+use files::Migration;
+use generator::{PassphraseSpec, PasswordSpec, Wordlist};
+use output::Output;
 use rlib::*;
 use rustyline::{error::ReadlineError, Editor};
-use std::path::PathBuf;
-use std::process::{Child, Command};
+use session::Session;
+use std::collections::hash_map::{self, HashMap};
+use std::io::{IsTerminal, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
 use std::string::String;
-use vault::{LockedVault, UnlockedVault};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use vault::{backup, Entry, Factors, Kdf, LockedVault, UnlockedVault};
+use zeroize::{Zeroize, Zeroizing};
+
+macro_rules! ignore {
+    ($x:expr) => {
+        let _ = $x;
+    };
+}
+
+/// Unlock `lv` with `keyfile` and the password given by the arguments,
+/// which is only prompted for when the vault uses one. Without a password
+/// argument a key cached by `rpw unlock` is tried first.
+fn unlock_vault(args: &ArgMatches, lv: &LockedVault, keyfile: Option<&Path>) -> Result<Session> {
+    if !cli::password_given(args) && (keyfile.is_none() || lv.factors.keyfile()) {
+        if let Some(cached) = keyring::load(&lv.name) {
+            match Session::new(lv, cached.key, cached.keyfile) {
+                Ok(session) => return Ok(session),
+                // Stale after the password was changed.
+                Err(_) => {
+                    ignore!(keyring::forget(&lv.name));
+                }
+            }
+        }
+    }
+    unlock_with_password(args, lv, keyfile)
+}
+
+/// Unlock `lv` with `keyfile` and the password given by the arguments,
+/// never with a cached key. Commands guarded by the password use it.
+fn unlock_with_password(
+    args: &ArgMatches,
+    lv: &LockedVault,
+    keyfile: Option<&Path>,
+) -> Result<Session> {
+    if keyfile.is_some() && !lv.factors.keyfile() {
+        return Err(Kind::Usage.error(format!(
+            "Vault {} does not use a keyfile, leave out --keyfile",
+            lv.name
+        )));
+    }
+    if lv.factors.keyfile() && keyfile.is_none() {
+        return Err(Kind::Auth.error(format!(
+            "Vault {} requires a keyfile, pass it with --keyfile",
+            lv.name
+        )));
+    }
+    let keyfile = keyfile.map(vault::keyfile).transpose()?;
+    let pass = if lv.factors.password() {
+        vault_password(args, &lv.name, "Please enter vault password (hidden):")?
+    } else {
+        Zeroizing::new(String::new())
+    };
+
+    let key = lv.key_with_keyfile(&pass, keyfile.as_deref())?;
+    Session::new(lv, key, keyfile)
+}
 
 struct ProgramState {
     cancelp: Option<Child>,
-    locked_vault: Option<LockedVault>,
-    master_pw: Option<String>,
+    /// Name of the vault opened for the interactive session.
+    vault: Option<String>,
+    /// Keyfile the interactive session was opened with.
+    keyfile: Option<PathBuf>,
+    session: Option<Session>,
+    /// Whether `--json` was given when rpw started, applying to every command
+    /// of an interactive session.
+    json: bool,
 }
 
 impl ProgramState {
     fn new() -> Self {
         ProgramState {
             cancelp: None,
-            locked_vault: None,
-            master_pw: None,
+            vault: None,
+            keyfile: None,
+            session: None,
+            json: false,
         }
     }
 }
 
-macro_rules! ignore {
-    ($x:expr) => {
-        let _ = $x;
+/// A password given as argument `name`, prompting for it when absent.
+fn password_arg(args: &ArgMatches, name: &str, prompt: &str) -> Result<Zeroizing<String>> {
+    match args.value_of(name) {
+        Some(p) => Ok(Zeroizing::new(p.to_string())),
+        None => cli::password(prompt),
+    }
+}
+
+/// The password of `vault`, read from where the arguments or
+/// `password_command` point to before prompting for it.
+fn vault_password(args: &ArgMatches, vault: &str, prompt: &str) -> Result<Zeroizing<String>> {
+    if args.is_present("password") {
+        return password_arg(args, "password", prompt);
+    }
+    match cli::read_password(args, vault)? {
+        Some(pass) => Ok(pass),
+        None => cli::password(prompt),
+    }
+}
+
+/// A new vault password, prompted for twice unless read non-interactively.
+fn new_vault_password(args: &ArgMatches, prompt: &str) -> Result<Zeroizing<String>> {
+    let read = if args.is_present("password") {
+        None
+    } else {
+        cli::given_password(args)?
+    };
+    let (pass, vfied) = match read {
+        Some(pass) => {
+            let vfied = match args.value_of("verify") {
+                Some(v) => Zeroizing::new(v.to_string()),
+                None => pass.clone(),
+            };
+            (pass, vfied)
+        }
+        _ => (
+            password_arg(args, "password", prompt)?,
+            password_arg(args, "verify", "Verify vault password (hidden):")?,
+        ),
     };
+
+    if pass != vfied {
+        return Err(Kind::Usage.error("Passwords do not match"));
+    }
+    Ok(pass)
+}
+
+fn vault_arg<'a>(args: &'a ArgMatches, state: &'a ProgramState) -> Result<&'a str> {
+    args.value_of("vault")
+        .or(state.vault.as_deref())
+        .ok_or_else(|| Kind::Usage.error("No vault given"))
+}
+
+/// The session of the open vault, unlocking the vault given by the
+/// arguments for one-shot commands.
+fn session<'a>(args: &ArgMatches, state: &'a mut ProgramState) -> Result<&'a mut Session> {
+    if state.session.is_none() {
+        let lv: LockedVault = vault_arg(args, state)?
+            .parse()
+            .context("Could not find vault")?;
+        let keyfile = args
+            .value_of("keyfile")
+            .map(PathBuf::from)
+            .or_else(|| state.keyfile.clone());
+        state.session = Some(unlock_vault(args, &lv, keyfile.as_deref())?);
+    }
+    let session = state.session.as_mut().unwrap();
+    session.refresh()?;
+    Ok(session)
+}
+
+/// The agent serving one-shot commands, when `RPW_AGENT_SOCK` is set. Open
+/// vaults hold their own key instead.
+fn agent_socket(state: &ProgramState) -> Option<PathBuf> {
+    if state.vault.is_some() {
+        return None;
+    }
+    agent::socket()
+}
+
+/// Send `request` to the agent, first handing it the key of the vault when
+/// it does not hold it yet.
+fn agent_request(sock: &Path, args: &ArgMatches, request: Request) -> Result<Response> {
+    if let Response::Locked = agent::request(sock, &request)? {
+        let lv: LockedVault = request.vault().parse().context("Could not find vault")?;
+        let session = unlock_vault(args, &lv, args.value_of("keyfile").map(Path::new))?;
+        agent::request(sock, &Request::unlock(&lv.name, &session.key))?;
+        return agent::request(sock, &request);
+    }
+    agent::request(sock, &request)
+}
+
+fn agent_vault(args: &ArgMatches) -> Result<String> {
+    Ok(args
+        .value_of("vault")
+        .ok_or_else(|| Kind::Usage.error("No vault given"))?
+        .to_string())
+}
+
+/// Entry `alias` of the vault, fetched through the agent when there is one.
+fn find_entry(args: &ArgMatches, state: &mut ProgramState, alias: &str) -> Result<Option<Entry>> {
+    if let Some(sock) = agent_socket(state) {
+        let request = Request::Get {
+            vault: agent_vault(args)?,
+            alias: alias.to_string(),
+        };
+        return match agent_request(&sock, args, request)? {
+            Response::Entry { entry } => Ok(entry),
+            _ => Err(anyhow!("Unexpected response from the agent")),
+        };
+    }
+    Ok(session(args, state)?.vault.get(alias.to_string()).cloned())
+}
+
+/// Entries of the vault, without their secrets when listed by the agent.
+fn all_entries(args: &ArgMatches, state: &mut ProgramState) -> Result<Vec<(String, Entry)>> {
+    if let Some(sock) = agent_socket(state) {
+        let request = Request::List {
+            vault: agent_vault(args)?,
+        };
+        return match agent_request(&sock, args, request)? {
+            Response::List { entries } => Ok(entries),
+            _ => Err(anyhow!("Unexpected response from the agent")),
+        };
+    }
+    let uv = &session(args, state)?.vault;
+    Ok(uv.pws.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+}
+
+fn store_entry(
+    args: &ArgMatches,
+    state: &mut ProgramState,
+    alias: String,
+    entry: Entry,
+    config: &Config,
+) -> Result<()> {
+    if let Some(sock) = agent_socket(state) {
+        let request = Request::Add {
+            vault: agent_vault(args)?,
+            alias,
+            entry,
+        };
+        agent_request(&sock, args, request)?;
+        return Ok(());
+    }
+    let session = session(args, state)?;
+    let mut uv = session.vault.clone();
+    uv.insert(alias, entry);
+    session.save(uv, config.backups)
+}
+
+/// Read lines on a separate thread, so the session can be locked while
+/// waiting for input. A new line is only read once the previous one has
+/// been acknowledged, leaving stdin free for password prompts meanwhile.
+fn spawn_reader(prompt: String) -> (Receiver<rustyline::Result<String>>, Sender<()>) {
+    let (line_tx, line_rx) = mpsc::channel();
+    let (ack_tx, ack_rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut rl = Editor::<()>::new();
+        loop {
+            let line = rl.readline(&prompt);
+            if line_tx.send(line).is_err() || ack_rx.recv().is_err() {
+                break;
+            }
+        }
+    });
+    (line_rx, ack_tx)
 }
 
 fn open(args: &ArgMatches, state: &mut ProgramState, config: &Config) -> Result<()> {
     let lv = value_t!(args.value_of("vault"), LockedVault).context("Could not find vault")?;
     let name = lv.name.clone();
-    state.locked_vault = Some(lv);
-    state.master_pw = Some(
-        value_t!(args.value_of("password"), String)
-            .unwrap_or_else(|_| cli::password("Please enter vault password (hidden):")),
-    );
+    let keyfile = args.value_of("keyfile").map(PathBuf::from);
+    state.session = Some(unlock_vault(args, &lv, keyfile.as_deref())?);
+    state.vault = Some(name.clone());
+    state.keyfile = keyfile;
 
-    state
-        .locked_vault
-        .as_ref()
-        .unwrap()
-        .unlock(state.master_pw.as_ref().unwrap())?;
     let app = cli::build();
-    let mut rl = Editor::<()>::new();
+    let idle = Duration::from_secs(config.idle_timeout);
+    let (lines, ack) = spawn_reader(format!("{}{}", &name, "$ "));
     loop {
-        let readline = rl.readline(&format!("{}{}", &name, "$ "));
+        let readline = if state.session.is_some() && config.idle_timeout > 0 {
+            match lines.recv_timeout(idle) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => {
+                    state.session = None;
+                    println!(
+                        "\nLocked vault {} after {} seconds of inactivity",
+                        name,
+                        idle.as_secs()
+                    );
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        } else {
+            match lines.recv() {
+                Ok(line) => line,
+                Err(_) => break,
+            }
+        };
+
         match readline {
             Ok(line) => {
                 let mut cmd = vec!["rpw"];
-                if line.trim().is_empty() {
-                    continue;
-                }
                 cmd.extend(line.split_whitespace());
 
-                let matches = app.clone().get_matches_from_safe(cmd);
-                match matches {
-                    Ok(m) => dispatch(&m, state, config),
-                    Err(msg) => println!("{}", msg),
-                };
-            }
-            Err(ReadlineError::Interrupted) => {
-                continue;
+                if !line.trim().is_empty() {
+                    let matches = app.clone().get_matches_from_safe(cmd);
+                    match matches {
+                        Ok(m) => {
+                            execute(&m, state, config);
+                        }
+                        Err(msg) => println!("{}", msg),
+                    };
+                }
             }
+            Err(ReadlineError::Interrupted) => {}
             Err(msg) => {
                 println!("{}, exiting", msg);
                 break;
             }
         }
+        ignore!(ack.send(()));
+    }
+    Ok(())
+}
+
+fn restore(
+    args: &ArgMatches,
+    state: &mut ProgramState,
+    config: &Config,
+    out: &mut Output,
+) -> Result<()> {
+    let name = vault_arg(args, state)?.to_string();
+    let backups = backup::list(&name)?;
+
+    let wanted = match args.value_of("backup") {
+        Some(b) => b,
+        None => {
+            out.say(format!("Backups of vault {}", name));
+            for (i, b) in backups.iter().enumerate() {
+                out.say(format!("\t{}\t{}", i, b.timestamp));
+            }
+            let stamps: Vec<&str> = backups.iter().map(|b| b.timestamp.as_str()).collect();
+            out.set("backups", stamps);
+            return Ok(());
+        }
+    };
+
+    let chosen = wanted
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| backups.get(i))
+        .or_else(|| backups.iter().find(|b| b.timestamp == wanted))
+        .ok_or_else(|| Kind::NotFound.error(format!("No backup '{}' of vault {}", wanted, name)))?;
+
+    if !cli::yesorno(&format!(
+        "Replace vault '{}' with the backup from {}?",
+        name, chosen.timestamp
+    ))? {
+        return Err(Kind::Declined.error("Did not restore vault"));
+    }
+
+    let _lock = LockedVault::acquire(&name)?;
+    backup::restore(&name, chosen, config.backups)?;
+    if state.vault.as_deref() == Some(name.as_str()) {
+        state.session = None;
     }
+    out.say(format!("Restored vault {} from {}", name, chosen.timestamp));
+    out.set("vault", &name);
+    out.set("backup", &chosen.timestamp);
     Ok(())
 }
 
-fn new(args: &ArgMatches) -> Result<()> {
+/// Cache the key of a vault in the kernel keyring, so the following
+/// commands neither prompt for the password nor derive the key.
+fn unlock(args: &ArgMatches, config: &Config, out: &mut Output) -> Result<()> {
+    let lv = value_t!(args.value_of("vault"), LockedVault).context("Could not find vault")?;
+    let ttl = value_t!(args.value_of("ttl"), u64).unwrap_or(config.keyring_ttl);
+    let session = unlock_vault(args, &lv, args.value_of("keyfile").map(Path::new))?;
+    let cached = keyring::Cached {
+        key: session.key,
+        keyfile: session.keyfile,
+    };
+    keyring::store(&lv.name, &cached, Duration::from_secs(ttl))?;
+    out.say(format!(
+        "Cached the key of vault {} for {} seconds",
+        lv.name, ttl
+    ));
+    out.set("vault", &lv.name);
+    out.set("ttl", ttl);
+    Ok(())
+}
+
+fn forget(args: &ArgMatches, state: &ProgramState, out: &mut Output) -> Result<()> {
+    let name = vault_arg(args, state)?;
+    let forgotten = keyring::forget(name)?;
+    if forgotten {
+        out.say(format!("Forgot the cached key of vault {}", name));
+    } else {
+        out.say(format!("No key of vault {} is cached", name));
+    }
+    out.set("vault", name);
+    out.set("forgotten", forgotten);
+    Ok(())
+}
+
+fn lock(state: &mut ProgramState, out: &mut Output) -> Result<()> {
+    let locked = state.session.take().is_some();
+    if locked {
+        out.say("Vault locked");
+    }
+    out.set("locked", locked);
+    Ok(())
+}
+
+fn new(args: &ArgMatches, config: &Config, out: &mut Output) -> Result<()> {
     let vault = value_t!(args.value_of("vault"), String).unwrap();
-    let pass = value_t!(args.value_of("password"), String)
-        .unwrap_or_else(|_| cli::password("Please choose vault password (hidden):"));
-    let vfied = value_t!(args.value_of("verify"), String)
-        .unwrap_or_else(|_| cli::password("Verify vault password (hidden):"));
+    let mut uv = UnlockedVault::with_kdf(&vault, config.kdf.clone());
+    let keyfile = args
+        .value_of("keyfile")
+        .map(|k| vault::keyfile(Path::new(k)))
+        .transpose()?;
+    uv.factors = match (keyfile.is_some(), args.is_present("no-password")) {
+        (false, _) => Factors::Password,
+        (true, false) => Factors::Both,
+        (true, true) => Factors::Keyfile,
+    };
 
-    if pass != vfied {
-        return Err(anyhow!("Passwords do not match"));
+    let mut pass = Zeroizing::new(String::new());
+    if uv.factors.password() {
+        if !cli::password_given(args) {
+            let (suggestion, bits) = config.passphrase.generate()?;
+            out.say(format!(
+                "Suggested password: {} ({:.1} bits of entropy)",
+                suggestion, bits
+            ));
+        }
+        pass = new_vault_password(args, "Please choose vault password (hidden):")?;
     }
 
-    let lv = UnlockedVault::new(&vault).lock(&pass)?;
+    let lv = uv.lock_with_key(&uv.key_with_keyfile(&pass, keyfile.as_deref())?)?;
     if lv.exists()
         && !cli::yesorno(&format!(
             "Vault '{}' already exists, would you like to overwrite it?",
             vault
-        ))
+        ))?
     {
-        println!("Aborting, not creating vault '{}'.", vault);
+        return Err(Kind::Declined.error(format!("Aborting, not creating vault '{}'.", vault)));
     }
-    lv.save()?;
-    println!("New vault {} created", vault);
+    let _lock = LockedVault::acquire(&vault)?;
+    lv.save_with_backup(config.backups)?;
+    out.say(format!("New vault {} created", vault));
+    out.set("vault", &vault);
     Ok(())
 }
 
-fn delete(args: &ArgMatches) -> Result<()> {
-    let vault = value_t!(args.value_of("vault"), LockedVault).context("Could not find vault")?;
-    let pass = value_t!(args.value_of("password"), String)
-        .unwrap_or_else(|_| cli::password("Please enter vault password (hidden):"));
-    let vfied = value_t!(args.value_of("verify"), String)
-        .unwrap_or_else(|_| cli::password("Verify vault password (hidden):"));
+fn delete(args: &ArgMatches, out: &mut Output) -> Result<()> {
+    let vault: LockedVault = args
+        .value_of("vault")
+        .unwrap()
+        .parse()
+        .context("Could not find vault")?;
+    unlock_with_password(args, &vault, args.value_of("keyfile").map(Path::new))?;
 
-    if pass != vfied {
-        return Err(anyhow!("Passwords do not match"));
+    vault.delete().context("Failed deleting vault.")?;
+    out.say(format!("Deleted vault {}", &vault.name));
+    out.set("vault", &vault.name);
+    Ok(())
+}
+
+fn add(
+    args: &ArgMatches,
+    state: &mut ProgramState,
+    config: &Config,
+    out: &mut Output,
+) -> Result<()> {
+    let alias = value_t!(args.value_of("alias"), String).unwrap();
+    let existing = find_entry(args, state, &alias)?;
+    let keep_password = existing.is_some()
+        && !args.is_present("new-password")
+        && !args.is_present("generate")
+        && ENTRY_ARGS.iter().any(|a| args.is_present(a));
+
+    let mut entry = existing.unwrap_or_else(|| Entry::new(String::new()));
+    if args.is_present("generate") {
+        entry.password = generate_password(args, config)?.0;
+    } else if !keep_password {
+        entry.password.zeroize();
+        entry.password.push_str(&password_arg(
+            args,
+            "new-password",
+            "Please enter new password (hidden):",
+        )?);
     }
+    set_entry_fields(&mut entry, args)?;
+    entry.touch();
 
-    vault.delete().context("Failed deleting vault.")?;
-    println!("Deleted vault {}", &vault.name);
+    out.set("alias", &alias);
+    store_entry(args, state, alias, entry, config)
+}
+
+const ENTRY_ARGS: [&str; 6] = ["username", "url", "notes", "tag", "field", "otp"];
+
+fn set_entry_fields(entry: &mut Entry, args: &ArgMatches) -> Result<()> {
+    if let Some(username) = args.value_of("username") {
+        entry.username = Some(username.to_string());
+    }
+    if let Some(urls) = args.values_of("url") {
+        entry.urls = urls.map(String::from).collect();
+    }
+    if let Some(notes) = args.value_of("notes") {
+        entry.notes = Some(notes.to_string());
+    }
+    if let Some(tags) = args.values_of("tag") {
+        entry.tags = tags.map(String::from).collect();
+    }
+    for field in args.values_of("field").into_iter().flatten() {
+        let (k, v) = field.split_once('=').ok_or_else(|| {
+            Kind::Usage.error(format!("Field '{}' is not of the form key=value", field))
+        })?;
+        entry.fields.insert(k.to_string(), v.to_string());
+    }
+    if let Some(otp) = args.value_of("otp") {
+        entry.otp = Some(otp.parse()?);
+    }
     Ok(())
 }
 
-fn add(args: &ArgMatches, state: &mut ProgramState) -> Result<()> {
-    if state.locked_vault.is_none() {
-        state.locked_vault =
-            Some(value_t!(args.value_of("vault"), LockedVault).context("Could not find vault")?);
+fn passwd(
+    args: &ArgMatches,
+    state: &mut ProgramState,
+    config: &Config,
+    out: &mut Output,
+) -> Result<()> {
+    // The current password is asked for even when a key is cached or the
+    // vault is open.
+    let lv: LockedVault = vault_arg(args, state)?
+        .parse()
+        .context("Could not find vault")?;
+    let keyfile = args.value_of("keyfile").map(PathBuf::from).or_else(|| {
+        let open = state.vault.as_deref() == Some(lv.name.as_str());
+        state.keyfile.clone().filter(|_| open)
+    });
+    let mut session = unlock_with_password(args, &lv, keyfile.as_deref())?;
+    let mut uv = session.vault.clone();
+    if !uv.factors.password() {
+        return Err(Kind::Usage.error(format!(
+            "Vault {} is unlocked by its keyfile alone, it has no password",
+            uv.name
+        )));
     }
 
-    let vault = state.locked_vault.as_ref().unwrap();
-    let mpass = state
-        .master_pw
-        .clone()
-        .ok_or_else(|| value_t!(args.value_of("password"), String))
-        .unwrap_or_else(|_| cli::password("Please enter vault password (hidden):"));
+    let npass = password_arg(
+        args,
+        "new-password",
+        "Please choose new vault password (hidden):",
+    )?;
+    let vfied = password_arg(args, "verify", "Verify new vault password (hidden):")?;
 
-    let alias = value_t!(args.value_of("alias"), String).unwrap();
-    let npass = value_t!(args.value_of("new-password"), String)
-        .unwrap_or_else(|_| cli::password("Please enter new password (hidden):"));
+    if npass != vfied {
+        return Err(Kind::Usage.error("Passwords do not match"));
+    }
 
-    let mut uv = vault.unlock(&mpass)?;
-    uv.insert(alias, npass);
-    uv.lock(&mpass)?.save()?;
+    uv.rekey();
+    if args.is_present("upgrade-kdf") {
+        uv.kdf = config.kdf.clone();
+    }
+
+    let key = uv.key_with_keyfile(&npass, session.keyfile.as_deref())?;
+    let _lock = LockedVault::acquire(&uv.name)?;
+    if session.changed()?.is_some() {
+        return Err(Kind::Conflict.error(format!(
+            "Vault {} was changed elsewhere, not changing its password",
+            uv.name
+        )));
+    }
+    let lv = uv.lock_with_key(&key)?;
+    lv.save_with_backup(config.backups)?;
+    uv.revision = Some(lv.revision().to_string());
+    ignore!(keyring::forget(&uv.name));
+    out.say(format!("Changed password of vault {}", &uv.name));
+    out.set("vault", &uv.name);
+    session.key = key;
+    session.vault = uv;
+    if state.vault.as_deref() == Some(session.vault.name.as_str()) {
+        state.session = Some(session);
+    }
     Ok(())
 }
 
-fn export(args: &ArgMatches, state: &mut ProgramState) -> Result<()> {
+fn export(args: &ArgMatches, state: &mut ProgramState, out: &mut Output) -> Result<()> {
     let fpath = value_t!(args.value_of("file-path"), PathBuf).unwrap();
-    if state.locked_vault.is_none() {
-        state.locked_vault =
-            Some(value_t!(args.value_of("vault"), LockedVault).context("Could not find vault")?);
-    }
-    let vault = state.locked_vault.as_ref().unwrap();
-    let mpass = state
-        .master_pw
-        .clone()
-        .ok_or_else(|| value_t!(args.value_of("password"), String))
-        .unwrap_or_else(|_| cli::password("Please enter vault password (hidden):"));
-
-    let uv = vault.unlock(&mpass)?;
-    uv.export(&fpath)?;
-    println!("Exported vault {}", &fpath.display());
+    let session = session(args, state)?;
+    session.vault.export(&fpath)?;
+    out.say(format!("Exported vault {}", &fpath.display()));
+    out.set("file", &fpath);
     Ok(())
 }
 
-fn import(args: &ArgMatches, state: &mut ProgramState) -> Result<()> {
+fn import(
+    args: &ArgMatches,
+    state: &mut ProgramState,
+    config: &Config,
+    out: &mut Output,
+) -> Result<()> {
     let fpath = value_t!(args.value_of("file"), PathBuf).unwrap();
-
-    if state.locked_vault.is_none() {
-        state.locked_vault =
-            Some(value_t!(args.value_of("vault"), LockedVault).context("Could not find vault")?);
-    }
-    let vault = state.locked_vault.as_ref().unwrap();
-
-    let mpass = state
-        .master_pw
-        .clone()
-        .ok_or_else(|| value_t!(args.value_of("password"), String))
-        .unwrap_or_else(|_| cli::password("Please enter vault password (hidden):"));
-
-    let mut uv = vault.unlock(&mpass).unwrap();
+    let session = session(args, state)?;
+    let mut uv = session.vault.clone();
     let dup = &uv.import(&fpath)?;
-    println!("Imported {} into vault", &fpath.display());
-    dup.iter().for_each(|p| {
+    out.say(format!("Imported {} into vault", &fpath.display()));
+    let mut overwritten = Vec::new();
+    for p in dup {
         if cli::yesorno(&format!(
             "Would you like to overwrite duplicate '{}'?",
             p.id
-        )) {
-            uv.insert(p.id.clone(), p.pw.clone());
+        ))? {
+            uv.insert(p.id.clone(), p.entry.clone());
+            overwritten.push(p.id.clone());
         }
-    });
-    uv.lock(&mpass)?.save()?;
+    }
+    session.save(uv, config.backups)?;
+    out.set("file", &fpath);
+    out.set("overwritten", overwritten);
     Ok(())
 }
 
-fn list(args: &ArgMatches, state: &mut ProgramState) -> Result<()> {
-    if state.locked_vault.is_none() {
-        state.locked_vault =
-            Some(value_t!(args.value_of("vault"), LockedVault).context("Could not find vault")?);
-    }
-
-    let vault = state.locked_vault.as_ref().unwrap();
-    let mpass = state
-        .master_pw
-        .clone()
-        .ok_or_else(|| value_t!(args.value_of("password"), String))
-        .unwrap_or_else(|_| cli::password("Please enter vault password (hidden):"));
+fn list(args: &ArgMatches, state: &mut ProgramState, out: &mut Output) -> Result<()> {
+    let tag = args.value_of("tag");
+    let mut entries = all_entries(args, state)?;
+    entries.retain(|(_, e)| tag.is_none_or(|t| e.tags.iter().any(|et| et == t)));
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
 
-    let uv = vault.unlock(&mpass).unwrap();
-    let ids: Vec<&String> = uv.pws.iter().map(|p| p.0).collect();
+    if out.json() {
+        let listed: Vec<serde_json::Value> = entries
+            .iter()
+            .map(|(id, e)| {
+                serde_json::json!({
+                    "alias": id,
+                    "username": e.username,
+                    "urls": e.urls,
+                    "tags": e.tags,
+                    "fields": e.fields.keys().collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        out.set("entries", listed);
+        return Ok(());
+    }
 
     println!("Stored passwords");
-    for id in ids {
+    for (id, entry) in entries {
         println!("\t{}", id);
+        if !args.is_present("long") {
+            continue;
+        }
+        if let Some(username) = &entry.username {
+            println!("\t\tusername: {}", username);
+        }
+        for url in &entry.urls {
+            println!("\t\turl: {}", url);
+        }
+        if !entry.tags.is_empty() {
+            println!("\t\ttags: {}", entry.tags.join(", "));
+        }
+        for k in entry.fields.keys() {
+            println!("\t\tfield: {}", k);
+        }
+    }
+    Ok(())
+}
+
+fn get(
+    args: &ArgMatches,
+    state: &mut ProgramState,
+    config: &Config,
+    out: &mut Output,
+) -> Result<()> {
+    let sec = clear_timeout(args, config)?;
+    let id = value_t!(args.value_of("alias"), String).unwrap();
+    let field = args.value_of("field").unwrap_or("password");
+    let entry = find_entry(args, state, &id)?
+        .ok_or_else(|| Kind::NotFound.error(format!("No entry '{}' in the vault", id)))?;
+    let value = Zeroizing::new(
+        entry
+            .field(field)
+            .ok_or_else(|| {
+                Kind::NotFound.error(format!("Entry '{}' has no field '{}'", id, field))
+            })?
+            .to_string(),
+    );
+    out.set("alias", &id);
+    out.set("field", field);
+    if args.is_present("stdout") {
+        return print_secret(&value, args.is_present("force"), out);
+    }
+    copy(&value, sec, state, config, out)
+}
+
+/// Run the command of the arguments with the variables of `--env` and
+/// `--mapping` set to secrets of the vault. One-shot commands are replaced
+/// by it, interactive sessions wait for it to finish.
+fn run(args: &ArgMatches, state: &mut ProgramState, out: &mut Output) -> Result<()> {
+    // Nothing would be left to print the result once replaced by the command.
+    if state.vault.is_none() && out.json() {
+        return Err(Kind::Usage.error("--json is only supported by run in an interactive session"));
+    }
+    let mut mappings = match args.value_of("mapping") {
+        Some(path) => env::parse_file(Path::new(path))?,
+        None => Vec::new(),
+    };
+    for m in args.values_of("env").into_iter().flatten() {
+        mappings.push(m.parse()?);
+    }
+    if mappings.is_empty() {
+        return Err(Kind::Usage.error("No variables given, pass --env or --mapping"));
+    }
+
+    let mut command: Vec<&str> = args.values_of("command").unwrap().collect();
+    let program = command.remove(0);
+    let mut child = Command::new(program);
+    child.args(command);
+    secret::allow_core_dumps(&mut child);
+    for m in &mappings {
+        let value = env::resolve(&m.secret, |alias| find_entry(args, state, alias))?;
+        child.env(&m.var, value.as_str());
+    }
+
+    if state.vault.is_none() {
+        let err = child.exec();
+        return Err(err).with_context(|| format!("Failed to run {}", program));
+    }
+    let status = child
+        .status()
+        .with_context(|| format!("Failed to run {}", program))?;
+    out.set("status", status.code());
+    if !status.success() {
+        return Err(anyhow!("{} failed, {}", program, status));
     }
     Ok(())
 }
 
-fn get(args: &ArgMatches, state: &mut ProgramState, config: &Config) -> Result<()> {
-    if state.locked_vault.is_none() {
-        state.locked_vault =
-            Some(value_t!(args.value_of("vault"), LockedVault).context("Could not find vault")?);
+/// Render the template of `--input` into `--output`, which only the user
+/// can read.
+fn inject(args: &ArgMatches, state: &mut ProgramState, out: &mut Output) -> Result<()> {
+    let input = Path::new(args.value_of("input").unwrap());
+    let output = Path::new(args.value_of("output").unwrap());
+    let text = std::fs::read_to_string(input)
+        .with_context(|| format!("Failed to read template {}", input.display()))?;
+
+    let mut sessions = HashMap::new();
+    let mut count = 0;
+    let rendered = template::render(&text, |r| {
+        count += 1;
+        env::resolve(&r.secret, |alias| {
+            vault_entry(args, state, &mut sessions, &r.vault, alias)
+        })
+    })?;
+
+    files::write_atomic(output, rendered.as_bytes())
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    out.say(format!("Wrote {} with {} secrets", output.display(), count));
+    out.set("file", output);
+    out.set("references", count);
+    Ok(())
+}
+
+/// Session of `vault` for commands on vaults other than the one given by
+/// `--vault`, unlocking it into `sessions` unless it is the open vault.
+fn vault_session<'a>(
+    args: &ArgMatches,
+    state: &'a mut ProgramState,
+    sessions: &'a mut HashMap<String, Session>,
+    vault: &str,
+) -> Result<&'a mut Session> {
+    if state.vault.as_deref() == Some(vault) {
+        return session(args, state);
+    }
+    let session = match sessions.entry(vault.to_string()) {
+        hash_map::Entry::Occupied(e) => e.into_mut(),
+        hash_map::Entry::Vacant(e) => {
+            // Commands using several vaults take --keyfile for those which
+            // use one.
+            let lv: LockedVault = vault.parse().context("Could not find vault")?;
+            let keyfile = args.value_of("keyfile").filter(|_| lv.factors.keyfile());
+            e.insert(unlock_vault(args, &lv, keyfile.map(Path::new))?)
+        }
+    };
+    session.refresh()?;
+    Ok(session)
+}
+
+/// The agent holding the key of `vault`, unless it is the open vault.
+fn vault_agent(state: &ProgramState, vault: &str) -> Option<PathBuf> {
+    if state.vault.as_deref() == Some(vault) {
+        return None;
+    }
+    agent::socket()
+}
+
+/// Entry `alias` of `vault`, from the open vault, the agent or else by
+/// unlocking the vault into `sessions`.
+fn vault_entry(
+    args: &ArgMatches,
+    state: &mut ProgramState,
+    sessions: &mut HashMap<String, Session>,
+    vault: &str,
+    alias: &str,
+) -> Result<Option<Entry>> {
+    if let Some(sock) = vault_agent(state, vault) {
+        let request = Request::Get {
+            vault: vault.to_string(),
+            alias: alias.to_string(),
+        };
+        return match agent_request(&sock, args, request)? {
+            Response::Entry { entry } => Ok(entry),
+            _ => Err(anyhow!("Unexpected response from the agent")),
+        };
+    }
+    let session = vault_session(args, state, sessions, vault)?;
+    Ok(session.vault.get(alias.to_string()).cloned())
+}
+
+/// Entries of `vault`, without their secrets when listed by the agent.
+fn vault_entries(
+    args: &ArgMatches,
+    state: &mut ProgramState,
+    sessions: &mut HashMap<String, Session>,
+    vault: &str,
+) -> Result<Vec<(String, Entry)>> {
+    if let Some(sock) = vault_agent(state, vault) {
+        let request = Request::List {
+            vault: vault.to_string(),
+        };
+        return match agent_request(&sock, args, request)? {
+            Response::List { entries } => Ok(entries),
+            _ => Err(anyhow!("Unexpected response from the agent")),
+        };
+    }
+    let uv = &vault_session(args, state, sessions, vault)?.vault;
+    Ok(uv.pws.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+}
+
+/// Store `entry` as `alias` of `vault`, removing `alias` when `None`.
+fn vault_update(
+    args: &ArgMatches,
+    state: &mut ProgramState,
+    sessions: &mut HashMap<String, Session>,
+    vault: &str,
+    alias: String,
+    entry: Option<Entry>,
+    backups: usize,
+) -> Result<()> {
+    if let Some(sock) = vault_agent(state, vault) {
+        let vault = vault.to_string();
+        let request = match entry {
+            Some(entry) => Request::Add {
+                vault,
+                alias,
+                entry,
+            },
+            None => Request::Remove { vault, alias },
+        };
+        agent_request(&sock, args, request)?;
+        return Ok(());
+    }
+    let session = vault_session(args, state, sessions, vault)?;
+    let mut uv = session.vault.clone();
+    match entry {
+        Some(entry) => uv.insert(alias, entry),
+        None => {
+            uv.remove(alias);
+        }
+    }
+    session.save(uv, backups)
+}
+
+/// A vault of git's credentials, reached like any vault of a command.
+struct GitStore<'a> {
+    args: &'a ArgMatches<'a>,
+    state: &'a mut ProgramState,
+    sessions: HashMap<String, Session>,
+    vault: String,
+    backups: usize,
+}
+
+impl git::Store for GitStore<'_> {
+    fn entries(&mut self) -> Result<Vec<(String, Entry)>> {
+        vault_entries(self.args, self.state, &mut self.sessions, &self.vault)
+    }
+
+    fn entry(&mut self, alias: &str) -> Result<Option<Entry>> {
+        vault_entry(
+            self.args,
+            self.state,
+            &mut self.sessions,
+            &self.vault,
+            alias,
+        )
+    }
+
+    fn update(&mut self, alias: String, entry: Option<Entry>) -> Result<()> {
+        vault_update(
+            self.args,
+            self.state,
+            &mut self.sessions,
+            &self.vault,
+            alias,
+            entry,
+            self.backups,
+        )
+    }
+}
+
+/// Answer git as credential helper, see git-credential(1). Only entries
+/// stored by git are ever erased.
+fn git_credential(
+    args: &ArgMatches,
+    state: &mut ProgramState,
+    config: &Config,
+    out: &mut Output,
+) -> Result<()> {
+    // Git reads the answer from stdout, which JSON would garble.
+    if out.json() {
+        return Err(Kind::Usage.error("--json is not supported by git-credential"));
     }
+    let vault = args
+        .value_of("vault")
+        .or(state.vault.as_deref())
+        .or(config.git_vault.as_deref())
+        .ok_or_else(|| {
+            Kind::Usage.error("No vault given, pass --vault or set git_vault in the configuration")
+        })?
+        .to_string();
+    let cred = git::Credential::read(std::io::stdin().lock())?;
+    let mut store = GitStore {
+        args,
+        state,
+        sessions: HashMap::new(),
+        vault,
+        backups: config.backups,
+    };
 
-    let vault = state.locked_vault.as_ref().unwrap();
+    match args.value_of("operation").unwrap() {
+        "get" => {
+            cred.get(&mut store, std::io::stdout())?;
+        }
+        "store" => {
+            cred.store(&mut store)?;
+        }
+        "erase" => {
+            cred.erase(&mut store)?;
+        }
+        // Helpers are to ignore operations they do not know.
+        _ => {}
+    }
+    Ok(())
+}
 
-    let mpass = state
-        .master_pw
-        .clone()
-        .ok_or_else(|| value_t!(args.value_of("password"), String))
-        .unwrap_or_else(|_| cli::password("Please enter vault password (hidden):"));
+/// Write `value` to stdout for piping, as is without a trailing newline.
+fn print_secret(value: &str, force: bool, out: &mut Output) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    if stdout.is_terminal() && !force {
+        return Err(Kind::Usage
+            .error("Refusing to print a secret to a terminal, pass --force to print it anyway"));
+    }
+    if out.json() {
+        out.set("value", value);
+        return Ok(());
+    }
+    stdout.write_all(value.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
 
-    let sec = value_t!(args.value_of("sec"), u64).unwrap_or_else(|_| config.clear_copy_timeout);
+fn otp(
+    args: &ArgMatches,
+    state: &mut ProgramState,
+    config: &Config,
+    out: &mut Output,
+) -> Result<()> {
+    let sec = clear_timeout(args, config)?;
     let id = value_t!(args.value_of("alias"), String).unwrap();
-    let uv = vault.unlock(&mpass)?;
-    let pass = uv.get(id).context("Failed to get password")?;
-    cli::xclip::to_clipboard(pass);
+    let entry = find_entry(args, state, &id)?
+        .ok_or_else(|| Kind::NotFound.error(format!("No entry '{}' in the vault", id)))?;
+    let totp = entry
+        .otp
+        .as_ref()
+        .ok_or_else(|| Kind::NotFound.error(format!("Entry '{}' has no OTP secret", id)))?;
+
+    let (code, valid) = totp.code()?;
+    out.say(format!("Code valid for {} seconds", valid));
+    out.set("alias", &id);
+    out.set("valid_for", valid);
+    copy(&code, sec, state, config, out)
+}
+
+fn copy(
+    value: &str,
+    sec: u64,
+    state: &mut ProgramState,
+    config: &Config,
+    out: &mut Output,
+) -> Result<()> {
+    let copied = Copied::copy(
+        &config.clipboard,
+        &keyring::Kernel,
+        value,
+        Duration::from_secs(sec),
+    )?;
     if let Some(cp) = state.cancelp.as_mut() {
         ignore!(cp.kill());
     }
-    println!("Clearing clipboard in {} seconds", sec);
-    state.cancelp = Some(do_clear(sec));
+    out.say(format!("Clearing clipboard in {} seconds", sec));
+    out.set("copied", true);
+    out.set("clear_after", sec);
+    state.cancelp = Some(do_clear(sec, &copied)?);
     Ok(())
 }
 
-fn do_clear(sleep: u64) -> Child {
-    Command::new("rpw")
+/// Clear the clipboard after `sleep` seconds in a separate process, which
+/// is told about the secret over stdin rather than its arguments.
+fn do_clear(sleep: u64, copied: &Copied) -> Result<Child> {
+    let exe = std::env::current_exe().context("Failed to find the rpw executable")?;
+    let mut child = Command::new(exe)
         .arg("clear")
         .arg(sleep.to_string())
+        .arg("--restore")
+        .stdin(Stdio::piped())
         .spawn()
-        .expect("Failed getting pw")
+        .context("Failed to start clearing the clipboard")?;
+
+    let json = Zeroizing::new(serde_json::to_string(copied)?);
+    child.stdin.take().unwrap().write_all(json.as_bytes())?;
+    Ok(child)
 }
 
-fn clear(args: &ArgMatches) -> Result<()> {
+/// Seconds until copied secrets are cleared, `--sec` or the configured
+/// timeout.
+fn clear_timeout(args: &ArgMatches, config: &Config) -> Result<u64> {
+    match args.value_of("sec") {
+        Some(_) => value_t!(args.value_of("sec"), u64)
+            .map_err(|_| Kind::Usage.error("--sec must be a number of seconds")),
+        None => Ok(config.clear_copy_timeout),
+    }
+}
+
+fn clear(args: &ArgMatches, config: &Config) -> Result<()> {
     let sec = value_t!(args.value_of("sec"), u64).unwrap();
+    let copied = if args.is_present("restore") {
+        let mut json = Zeroizing::new(String::new());
+        std::io::stdin().read_to_string(&mut json)?;
+        Some(serde_json::from_str::<Copied>(&json).context("Malformed clipboard state")?)
+    } else {
+        None
+    };
+
     let dur = std::time::Duration::from_secs(sec);
     std::thread::sleep(dur);
-    cli::xclip::to_clipboard("cleared");
+    match copied {
+        Some(copied) => copied
+            .clear(&config.clipboard, &keyring::Kernel)
+            .map(|_| ()),
+        None => config.clipboard.clear(),
+    }
+}
+
+fn password_spec(args: &ArgMatches, config: &Config) -> Result<PasswordSpec> {
+    let mut spec = config.generator.clone();
+    if args.is_present("length") {
+        spec.length = value_t!(args.value_of("length"), usize).context("Invalid length")?;
+    }
+    if args.is_present("exclude-ambiguous") {
+        spec.exclude_ambiguous = true;
+    }
+
+    let classes = [
+        (
+            "no-lowercase",
+            "min-lowercase",
+            &mut spec.lowercase,
+            &mut spec.min_lowercase,
+        ),
+        (
+            "no-uppercase",
+            "min-uppercase",
+            &mut spec.uppercase,
+            &mut spec.min_uppercase,
+        ),
+        (
+            "no-digits",
+            "min-digits",
+            &mut spec.digits,
+            &mut spec.min_digits,
+        ),
+        (
+            "no-symbols",
+            "min-symbols",
+            &mut spec.symbols,
+            &mut spec.min_symbols,
+        ),
+    ];
+    for (no, min, enabled, count) in classes {
+        if args.is_present(no) {
+            *enabled = false;
+            *count = 0;
+        }
+        if args.is_present(min) {
+            *count = value_t!(args.value_of(min), usize)
+                .with_context(|| format!("Invalid value for --{}", min))?;
+        }
+    }
+    Ok(spec)
+}
+
+fn passphrase_spec(args: &ArgMatches, config: &Config) -> Result<PassphraseSpec> {
+    let mut spec = config.passphrase.clone();
+    if args.is_present("words") {
+        spec.words = value_t!(args.value_of("words"), usize).context("Invalid word count")?;
+    }
+    if let Some(sep) = args.value_of("separator") {
+        spec.separator = sep.to_string();
+    }
+    if args.is_present("capitalize") {
+        spec.capitalize = true;
+    }
+    if args.is_present("digit") {
+        spec.digit = true;
+    }
+    if let Some(list) = args.value_of("wordlist") {
+        spec.wordlist = Wordlist::from(list);
+    }
+    Ok(spec)
+}
+
+/// Generate a password or passphrase, along with its entropy in bits when
+/// known.
+fn generate_password(args: &ArgMatches, config: &Config) -> Result<(String, Option<f64>)> {
+    if args.is_present("passphrase") {
+        let (pw, bits) = passphrase_spec(args, config)?.generate()?;
+        return Ok((pw, Some(bits)));
+    }
+    Ok((password_spec(args, config)?.generate()?, None))
+}
+
+fn generate(args: &ArgMatches, config: &Config, out: &mut Output) -> Result<()> {
+    let (pw, bits) = generate_password(args, config)?;
+    if out.json() {
+        out.set("password", &pw);
+        out.set("bits", bits);
+        return Ok(());
+    }
+    println!("{}", pw);
+    if let Some(bits) = bits {
+        eprintln!("Entropy: {:.1} bits", bits);
+    }
+    Ok(())
+}
+
+fn kdf(args: &ArgMatches, config: &Config, out: &mut Output) -> Result<()> {
+    match args.subcommand() {
+        ("tune", Some(args)) => {
+            let ms = value_t!(args.value_of("target"), u64)
+                .map_err(|_| Kind::Usage.error("Invalid target time"))?;
+            eprintln!("Benchmarking key derivation, this may take a while");
+            let kdf = Kdf::tune(Duration::from_millis(ms))?;
+            let mut config = config.clone();
+            config.kdf = kdf;
+            config.save()?;
+            out.say(format!("New vaults will use {}", config.kdf));
+            out.set("kdf", &config.kdf);
+            Ok(())
+        }
+        _ => Err(Kind::Usage.error("Unrecognized kdf command")),
+    }
+}
+
+fn run_agent(
+    args: &ArgMatches,
+    state: &ProgramState,
+    config: &Config,
+    out: &mut Output,
+) -> Result<()> {
+    if state.vault.is_some() {
+        return Err(Kind::Usage.error("The agent can not be started from an open vault"));
+    }
+    let sock = match args.value_of("socket") {
+        Some(s) => PathBuf::from(s),
+        None => agent::default_socket()?,
+    };
+    let ttl = value_t!(args.value_of("ttl"), u64).unwrap_or(config.agent_ttl);
+    let listener = agent::bind(&sock)?;
+
+    out.say(format!(
+        "{}={}; export {};",
+        agent::SOCK_VAR,
+        sock.display(),
+        agent::SOCK_VAR
+    ));
+    out.set("socket", &sock);
+    if !args.is_present("foreground") {
+        if let Some(pid) = agent::daemonize()? {
+            out.say(format!("echo Agent pid {};", pid));
+            out.set("pid", pid);
+            return Ok(());
+        }
+        return agent::serve(listener, Duration::from_secs(ttl), config.backups);
+    }
+    // Report before serving, which only ends with the agent.
+    out.finish(&Ok(()));
+    agent::serve(listener, Duration::from_secs(ttl), config.backups)
+}
+
+fn doctor(args: &ArgMatches, out: &mut Output) -> Result<()> {
+    let issues = files::permission_issues()?;
+    if issues.is_empty() {
+        out.say(format!("No problems found in {}", files::rpwd()?.display()));
+    }
+
+    let mut reported = Vec::new();
+    for issue in &issues {
+        out.say(issue.to_string());
+        let fixed = args.is_present("fix") && !issue.foreign_owner;
+        if fixed {
+            issue.fix()?;
+            out.say("\tfixed");
+        }
+        reported.push(serde_json::json!({
+            "path": issue.path,
+            "mode": format!("{:o}", issue.mode),
+            "expected": format!("{:o}", issue.expected),
+            "foreign_owner": issue.foreign_owner,
+            "fixed": fixed,
+        }));
+    }
+    out.set("issues", reported);
+    Ok(())
+}
+
+/// Refuse to touch files owned by someone else, and complain about files
+/// others can read.
+fn check_permissions() -> Result<()> {
+    let issues = files::permission_issues()?;
+    if let Some(foreign) = issues.iter().find(|i| i.foreign_owner) {
+        return Err(Kind::Permission.error(format!("Refusing to operate, {}", foreign)));
+    }
+    for issue in &issues {
+        eprintln!("WARNING: {}", issue);
+    }
+    if !issues.is_empty() {
+        eprintln!("WARNING: Others may read your vaults, run 'rpw doctor --fix'");
+    }
     Ok(())
 }
 
-fn dispatch(matches: &ArgMatches, state: &mut ProgramState, config: &Config) {
+fn dispatch(
+    matches: &ArgMatches,
+    state: &mut ProgramState,
+    config: &Config,
+    out: &mut Output,
+) -> Result<()> {
     match matches.subcommand() {
         ("open", Some(sargs)) => open(sargs, state, config),
-        ("new", Some(sargs)) => new(sargs),
-        ("delete", Some(args)) => delete(args),
-        ("export", Some(args)) => export(args, state),
-        ("import", Some(args)) => import(args, state),
-        ("add", Some(sargs)) => add(sargs, state),
-        ("get", Some(args)) => get(args, state, config),
-        ("list", Some(args)) => list(args, state),
-        ("clear", Some(args)) => clear(args),
-        _ => Err(anyhow!("Unrecognized command")),
-    }
-    .unwrap_or_else(|e| {
-        println!("{}", e);
-    });
+        ("new", Some(sargs)) => new(sargs, config, out),
+        ("delete", Some(args)) => delete(args, out),
+        ("export", Some(args)) => export(args, state, out),
+        ("import", Some(args)) => import(args, state, config, out),
+        ("restore", Some(args)) => restore(args, state, config, out),
+        ("add", Some(sargs)) => add(sargs, state, config, out),
+        ("generate", Some(args)) => generate(args, config, out),
+        ("passwd", Some(args)) => passwd(args, state, config, out),
+        ("get", Some(args)) => get(args, state, config, out),
+        ("otp", Some(args)) => otp(args, state, config, out),
+        ("run", Some(args)) => run(args, state, out),
+        ("inject", Some(args)) => inject(args, state, out),
+        ("git-credential", Some(args)) => git_credential(args, state, config, out),
+        ("list", Some(args)) => list(args, state, out),
+        ("clear", Some(args)) => clear(args, config),
+        ("lock", Some(_)) => lock(state, out),
+        ("unlock", Some(args)) => unlock(args, config, out),
+        ("forget", Some(args)) => forget(args, state, out),
+        ("kdf", Some(args)) => kdf(args, config, out),
+        ("doctor", Some(args)) => doctor(args, out),
+        ("agent", Some(args)) => run_agent(args, state, config, out),
+        _ => Err(Kind::Usage.error("Unrecognized command")),
+    }
+}
+
+/// Run the command of `matches` and report its outcome, returning the exit
+/// code.
+fn execute(matches: &ArgMatches, state: &mut ProgramState, config: &Config) -> i32 {
+    let mut out = Output::new(state.json || matches.is_present("json"));
+    let result = dispatch(matches, state, config, &mut out);
+    out.finish(&result)
+}
+
+/// Prepare the rpw directories and load the configuration.
+fn setup(matches: &ArgMatches) -> Result<Config> {
+    // Set for the whole process, so that spawned rpw processes inherit it.
+    if let Some(home) = matches.value_of("home") {
+        std::env::set_var(files::HOME_VAR, home);
+    }
+    match files::migrate_legacy().context("Failed to migrate rpw files")? {
+        Some(Migration::Moved(legacy)) => eprintln!(
+            "Moved vaults from {} to {} and the configuration to {}",
+            legacy.display(),
+            files::rpwd()?.display(),
+            files::config_dir()?.display()
+        ),
+        Some(Migration::Skipped(legacy)) => eprintln!(
+            "WARNING: Not moving {} as {} or {} already exist, move its vaults by hand",
+            legacy.display(),
+            files::rpwd()?.display(),
+            files::config_dir()?.display()
+        ),
+        None => {}
+    }
+
+    // Before the configuration is read or written, so that files owned by
+    // someone else are not trusted or touched.
+    if matches.subcommand_name() != Some("doctor") {
+        check_permissions()?;
+    }
+    let config = Config::load_or_create()?;
+    cli::set_password_command(config.password_command.clone());
+    Ok(config)
 }
 
 fn main() {
+    secret::disable_core_dumps();
     let mut state = ProgramState::new();
-    let config: Result<Config, Config> = Config::load().or_else(|_| Ok(Config::new().save()));
     let app = cli::build();
-    let matches = app.clone().get_matches_safe();
+    let m = match app.get_matches_safe() {
+        Ok(m) => m,
+        Err(e) => match e.kind {
+            clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => {
+                println!("{}", e.message);
+                return;
+            }
+            _ => {
+                eprintln!("{}", e.message);
+                std::process::exit(Kind::Usage.code());
+            }
+        },
+    };
+    state.json = m.is_present("json");
+    cli::set_noninteractive(m.is_present("yes"), m.is_present("no-input"));
 
-    match matches {
-        Ok(m) => dispatch(&m, &mut state, &config.unwrap()),
-        Err(msg) => println!("{}", msg),
+    let code = match setup(&m) {
+        Ok(config) => execute(&m, &mut state, &config),
+        Err(e) => Output::new(state.json).finish(&Err(e)),
     };
+    // Exiting skips destructors, drop the session first to wipe its key.
+    drop(state);
+    std::process::exit(code);
 }
@@ -0,0 +1,213 @@
+use anyhow::{anyhow, Context, Result};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroize;
+
+const BASE32: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    SHA1,
+    SHA256,
+    SHA512,
+}
+
+impl Algorithm {
+    fn digest(&self) -> MessageDigest {
+        match self {
+            Algorithm::SHA1 => MessageDigest::sha1(),
+            Algorithm::SHA256 => MessageDigest::sha256(),
+            Algorithm::SHA512 => MessageDigest::sha512(),
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "SHA1" => Ok(Algorithm::SHA1),
+            "SHA256" => Ok(Algorithm::SHA256),
+            "SHA512" => Ok(Algorithm::SHA512),
+            _ => Err(anyhow!("Unsupported OTP algorithm '{}'", s)),
+        }
+    }
+}
+
+fn default_algorithm() -> Algorithm {
+    Algorithm::SHA1
+}
+
+fn default_digits() -> u32 {
+    6
+}
+
+fn default_period() -> u64 {
+    30
+}
+
+/// RFC 6238 time based one-time password parameters.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Totp {
+    /// Base32 encoded shared secret.
+    pub secret: String,
+    #[serde(default = "default_algorithm")]
+    pub algorithm: Algorithm,
+    #[serde(default = "default_digits")]
+    pub digits: u32,
+    #[serde(default = "default_period")]
+    pub period: u64,
+}
+
+fn base32_decode(s: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut buf: u64 = 0;
+    let mut bits = 0;
+
+    for c in s.bytes().filter(|c| !b" =-".contains(c)) {
+        let v = BASE32
+            .iter()
+            .position(|b| *b == c.to_ascii_uppercase())
+            .ok_or_else(|| anyhow!("Invalid base32 character '{}'", c as char))?;
+        buf = (buf << 5) | v as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buf >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+fn percent_decode(s: &str) -> Result<String> {
+    let mut out = Vec::new();
+    let mut bytes = s.bytes();
+
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                if hex.len() != 2 || !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return Err(anyhow!("Invalid percent encoding in URI"));
+                }
+                let hex = std::str::from_utf8(&hex).unwrap();
+                out.push(u8::from_str_radix(hex, 16).unwrap());
+            }
+            b'+' => out.push(b' '),
+            _ => out.push(b),
+        }
+    }
+    String::from_utf8(out).context("Invalid UTF8 in URI")
+}
+
+impl Totp {
+    pub fn new(secret: &str) -> Result<Self> {
+        let totp = Totp {
+            secret: secret.replace(' ', "").to_ascii_uppercase(),
+            algorithm: default_algorithm(),
+            digits: default_digits(),
+            period: default_period(),
+        };
+        totp.validate()?;
+        Ok(totp)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if base32_decode(&self.secret)?.is_empty() {
+            return Err(anyhow!("Empty OTP secret"));
+        }
+        if !(1..=9).contains(&self.digits) {
+            return Err(anyhow!("OTP digits must be between 1 and 9"));
+        }
+        if self.period == 0 {
+            return Err(anyhow!("OTP period must be positive"));
+        }
+        Ok(())
+    }
+
+    /// Parse an `otpauth://totp/...` URI as exported by most authenticators.
+    pub fn from_uri(uri: &str) -> Result<Self> {
+        let rest = uri
+            .strip_prefix("otpauth://")
+            .ok_or_else(|| anyhow!("Not an otpauth URI"))?;
+        let (kind, rest) = rest
+            .split_once('/')
+            .ok_or_else(|| anyhow!("Malformed otpauth URI"))?;
+        if !kind.eq_ignore_ascii_case("totp") {
+            return Err(anyhow!("Unsupported OTP type '{}'", kind));
+        }
+
+        let query = rest.split_once('?').map_or("", |(_, q)| q);
+        let mut secret = None;
+        let mut totp = Totp {
+            secret: String::new(),
+            algorithm: default_algorithm(),
+            digits: default_digits(),
+            period: default_period(),
+        };
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            let v = percent_decode(v)?;
+            match k {
+                "secret" => secret = Some(v),
+                "algorithm" => totp.algorithm = v.parse()?,
+                "digits" => totp.digits = v.parse().context("Invalid OTP digits")?,
+                "period" => totp.period = v.parse().context("Invalid OTP period")?,
+                _ => {}
+            }
+        }
+
+        let secret = secret.ok_or_else(|| anyhow!("otpauth URI lacks a secret"))?;
+        totp.secret = secret.replace(' ', "").to_ascii_uppercase();
+        totp.validate()?;
+        Ok(totp)
+    }
+
+    /// One-time code for the given unix time.
+    pub fn code_at(&self, time: u64) -> Result<String> {
+        let key = PKey::hmac(&base32_decode(&self.secret)?)?;
+        let mut signer = Signer::new(self.algorithm.digest(), &key)?;
+        signer.update(&(time / self.period).to_be_bytes())?;
+        let mac = signer.sign_to_vec()?;
+
+        let offset = (mac[mac.len() - 1] & 0xf) as usize;
+        let bin = u32::from_be_bytes([
+            mac[offset] & 0x7f,
+            mac[offset + 1],
+            mac[offset + 2],
+            mac[offset + 3],
+        ]);
+        let code = bin % 10u32.pow(self.digits);
+        Ok(format!("{:0width$}", code, width = self.digits as usize))
+    }
+
+    /// The current code and the number of seconds it remains valid.
+    pub fn code(&self) -> Result<(String, u64)> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("System clock is before the unix epoch")?
+            .as_secs();
+        Ok((self.code_at(now)?, self.period - now % self.period))
+    }
+}
+
+impl Drop for Totp {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+impl FromStr for Totp {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("otpauth://") {
+            Totp::from_uri(s)
+        } else {
+            Totp::new(s)
+        }
+    }
+}
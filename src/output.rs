@@ -0,0 +1,83 @@
+use crate::error::Kind;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+/// What a command reports. Messages are printed right away, unless JSON
+/// was asked for, in which case a single object holding the results of the
+/// command is printed once it finished.
+pub struct Output {
+    json: bool,
+    messages: Vec<String>,
+    data: Map<String, Value>,
+    printed: bool,
+}
+
+impl Output {
+    pub fn new(json: bool) -> Self {
+        Output {
+            json,
+            messages: Vec::new(),
+            data: Map::new(),
+            printed: false,
+        }
+    }
+
+    pub fn json(&self) -> bool {
+        self.json
+    }
+
+    /// A human readable message, listed under `messages` in JSON output.
+    pub fn say(&mut self, msg: impl Into<String>) {
+        let msg = msg.into();
+        if self.json {
+            self.messages.push(msg);
+        } else {
+            println!("{}", msg);
+        }
+    }
+
+    /// A result of the command, only part of JSON output.
+    pub fn set(&mut self, key: &str, value: impl Serialize) {
+        if self.json {
+            let value = serde_json::to_value(value).unwrap_or(Value::Null);
+            self.data.insert(key.to_string(), value);
+        }
+    }
+
+    /// Print the outcome of the command, returning the exit code.
+    pub fn finish(&mut self, result: &anyhow::Result<()>) -> i32 {
+        let code = match result {
+            Ok(()) => 0,
+            Err(e) => Kind::of(e).code(),
+        };
+        if !self.json {
+            if let Err(e) = result {
+                eprintln!("{}", e);
+            }
+            return code;
+        }
+        if self.printed && result.is_ok() {
+            return code;
+        }
+
+        let mut doc = std::mem::take(&mut self.data);
+        doc.insert(String::from("ok"), json!(result.is_ok()));
+        if !self.messages.is_empty() {
+            doc.insert(String::from("messages"), json!(self.messages));
+        }
+        if let Err(e) = result {
+            doc.insert(
+                String::from("error"),
+                json!({
+                    "kind": Kind::of(e),
+                    "code": code,
+                    "message": format!("{:#}", e),
+                }),
+            );
+        }
+        println!("{}", Value::Object(doc));
+        self.messages.clear();
+        self.printed = true;
+        code
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+#[cfg(unix)]
+use std::sync::OnceLock;
+use zeroize::Zeroize;
+
+/// Secret bytes, such as a derived vault key, which are kept out of swap
+/// where the system permits and wiped from memory when dropped.
+pub struct SecretBytes(Box<[u8]>);
+
+impl SecretBytes {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        let secret = SecretBytes(bytes.into_boxed_slice());
+        #[cfg(unix)]
+        unsafe {
+            // Failing to lock, e.g. due to RLIMIT_MEMLOCK, is not fatal.
+            libc::mlock(secret.0.as_ptr() as *const libc::c_void, secret.0.len());
+        }
+        secret
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl DerefMut for SecretBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl Clone for SecretBytes {
+    fn clone(&self) -> Self {
+        SecretBytes::new(self.0.to_vec())
+    }
+}
+
+impl PartialEq for SecretBytes {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len() && openssl::memcmp::eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretBytes([REDACTED; {}])", self.0.len())
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        #[cfg(unix)]
+        unsafe {
+            libc::munlock(self.0.as_ptr() as *const libc::c_void, self.0.len());
+        }
+    }
+}
+
+/// The core dump limit rpw was started with, before `disable_core_dumps`.
+#[cfg(unix)]
+static CORE_LIMIT: OnceLock<libc::rlimit> = OnceLock::new();
+
+/// Keep secrets out of core dumps, and on Linux out of reach of ptrace from
+/// other processes of the same user. Only the soft limit is lowered, so
+/// that commands run by rpw can be given back the one it was started with.
+pub fn disable_core_dumps() {
+    #[cfg(unix)]
+    unsafe {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if libc::getrlimit(libc::RLIMIT_CORE, &mut limit) == 0 {
+            let _ = CORE_LIMIT.set(limit);
+            limit.rlim_cur = 0;
+        }
+        libc::setrlimit(libc::RLIMIT_CORE, &limit);
+    }
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+    }
+}
+
+/// Run `cmd` with the core dump limit rpw was started with.
+pub fn allow_core_dumps(cmd: &mut Command) {
+    #[cfg(unix)]
+    if let Some(limit) = CORE_LIMIT.get().copied() {
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::setrlimit(libc::RLIMIT_CORE, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::error::Kind;
+use crate::secret::SecretBytes;
+use crate::vault::{LockedVault, UnlockedVault};
+use anyhow::{Context, Result};
+
+/// An unlocked vault along with its derived key, so that commands within a
+/// session neither keep the password around nor derive the key again.
+pub struct Session {
+    pub key: SecretBytes,
+    pub vault: UnlockedVault,
+    /// Digest of the keyfile, for vaults which require one.
+    pub keyfile: Option<SecretBytes>,
+}
+
+impl Session {
+    /// Unlock `lv` with its derived key.
+    pub fn new(lv: &LockedVault, key: SecretBytes, keyfile: Option<SecretBytes>) -> Result<Self> {
+        let vault = lv.unlock_with_key(&key)?;
+        Ok(Session {
+            key,
+            vault,
+            keyfile,
+        })
+    }
+
+    /// The stored vault, when another process saved it since the session
+    /// last read or wrote it.
+    pub fn changed(&self) -> Result<Option<UnlockedVault>> {
+        let name = &self.vault.name;
+        let stored: LockedVault = name.parse().context("Could not find vault")?;
+        if self.vault.is_revision_of(&stored) {
+            return Ok(None);
+        }
+        stored.unlock_with_key(&self.key).map(Some).map_err(|_| {
+            Kind::Conflict.error(format!(
+                "Vault {} was re-keyed elsewhere, open it again",
+                name
+            ))
+        })
+    }
+
+    /// Pick up changes other processes saved to the vault.
+    pub fn refresh(&mut self) -> Result<()> {
+        if let Some(stored) = self.changed()? {
+            self.vault = stored;
+        }
+        Ok(())
+    }
+
+    /// Encrypt and store `vault`, making it the current state of the session.
+    /// Changes saved by other processes meanwhile are merged, refusing to
+    /// save when they touch the same entries.
+    pub fn save(&mut self, mut vault: UnlockedVault, backups: usize) -> Result<()> {
+        let _lock = LockedVault::acquire(&vault.name)?;
+        if let Some(stored) = self.changed()? {
+            let conflicts = vault.merge(&self.vault, &stored);
+            if !conflicts.is_empty() {
+                self.vault = stored;
+                return Err(Kind::Conflict.error(format!(
+                    "Vault {} was changed elsewhere, not saving conflicting changes to {}",
+                    vault.name,
+                    conflicts.join(", ")
+                )));
+            }
+            eprintln!("Merged changes saved elsewhere to vault {}", vault.name);
+        }
+
+        let lv = vault.lock_with_key(&self.key)?;
+        lv.save_with_backup(backups)?;
+        vault.revision = Some(lv.revision().to_string());
+        self.vault = vault;
+        Ok(())
+    }
+}
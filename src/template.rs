@@ -0,0 +1,93 @@
+//! Templates with `{{ rpw://vault/alias/field }}` placeholders for secrets,
+//! rendered by `rpw inject`.
+use crate::error::Kind;
+use anyhow::Result;
+use std::fmt;
+use zeroize::Zeroizing;
+
+const SCHEME: &str = "rpw://";
+
+/// A secret of a vault, `ALIAS` or `ALIAS/FIELD` as in `rpw run`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub vault: String,
+    pub secret: String,
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}/{}", SCHEME, self.vault, self.secret)
+    }
+}
+
+impl Reference {
+    /// The reference of placeholder contents, `None` for placeholders of
+    /// other template languages.
+    fn parse(s: &str) -> Result<Option<Self>> {
+        let rest = match s.strip_prefix(SCHEME) {
+            Some(rest) => rest,
+            None => return Ok(None),
+        };
+        match rest.split_once('/') {
+            Some((vault, secret)) if !vault.is_empty() && !secret.is_empty() => {
+                Ok(Some(Reference {
+                    vault: vault.to_string(),
+                    secret: secret.to_string(),
+                }))
+            }
+            _ => Err(Kind::Usage.error(format!(
+                "'{}' is not of the form {}VAULT/ALIAS[/FIELD]",
+                s, SCHEME
+            ))),
+        }
+    }
+}
+
+/// `template` with its references replaced by what `resolve` returns for
+/// them. Fails listing every reference which could not be found.
+pub fn render<F>(template: &str, mut resolve: F) -> Result<Zeroizing<String>>
+where
+    F: FnMut(&Reference) -> Result<Zeroizing<String>>,
+{
+    let mut out = Zeroizing::new(String::with_capacity(template.len()));
+    let mut unresolved = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let offset = template.len() - rest.len() + start;
+        let line = template[..offset].matches('\n').count() + 1;
+        out.push_str(&rest[..start]);
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None if rest[start + 2..].trim_start().starts_with(SCHEME) => {
+                return Err(Kind::Usage.error(format!("Unterminated placeholder on line {}", line)));
+            }
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+
+        let placeholder = &rest[start..end + 2];
+        match Reference::parse(rest[start + 2..end].trim())? {
+            Some(reference) => match resolve(&reference) {
+                Ok(value) => out.push_str(&value),
+                Err(e) if Kind::of(&e) == Kind::NotFound => {
+                    unresolved.push(format!("line {}: {}, {:#}", line, reference, e));
+                }
+                Err(e) => return Err(e),
+            },
+            None => out.push_str(placeholder),
+        }
+        rest = &rest[end + 2..];
+    }
+    out.push_str(rest);
+
+    if !unresolved.is_empty() {
+        return Err(Kind::NotFound.error(format!(
+            "Unresolved references\n\t{}",
+            unresolved.join("\n\t")
+        )));
+    }
+    Ok(out)
+}
@@ -0,0 +1,135 @@
+use super::{LockedVault, VAULT_EXT};
+use crate::files;
+use anyhow::{anyhow, Context, Result};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Length of the timestamps in backup names, e.g. 20210301T120000Z.
+const TIMESTAMP_LEN: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub timestamp: String,
+}
+
+/// UTC time formatted as a compact ISO 8601 timestamp.
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    timestamp_at(secs)
+}
+
+/// `secs` since the epoch formatted like `timestamp`.
+pub fn timestamp_at(secs: u64) -> String {
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since the epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Backups of the vault `name`, newest first.
+pub fn list(name: &str) -> Result<Vec<Backup>> {
+    let dir = files::backups_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let prefix = format!("{}-", name);
+    let mut backups: Vec<Backup> = std::fs::read_dir(&dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let fname = e.file_name().to_string_lossy().to_string();
+            let stamp = fname.strip_prefix(&prefix)?.strip_suffix(VAULT_EXT)?;
+            let ts = stamp.get(..TIMESTAMP_LEN)?;
+            let seq = &stamp[TIMESTAMP_LEN..];
+            if !ts.ends_with('Z') || !seq.chars().all(|c| c == '.' || c.is_ascii_digit()) {
+                return None;
+            }
+            Some(Backup {
+                path: e.path(),
+                timestamp: stamp.to_string(),
+            })
+        })
+        .collect();
+
+    backups.sort_by_key(|b| {
+        let ts = &b.timestamp[..TIMESTAMP_LEN];
+        std::cmp::Reverse((ts.to_string(), sequence(&b.timestamp)))
+    });
+    Ok(backups)
+}
+
+/// Number of a backup among those of the same second, 0 for the first.
+fn sequence(timestamp: &str) -> u32 {
+    timestamp[TIMESTAMP_LEN..]
+        .trim_start_matches('.')
+        .parse()
+        .unwrap_or(0)
+}
+
+/// Copy the current file of the vault `name` into the backups, keeping at
+/// most `keep` of them.
+pub fn create(name: &str, keep: usize) -> Result<()> {
+    let current = files::rpwd_path(&format!("{}{}", name, VAULT_EXT))?;
+    if keep == 0 || !current.exists() {
+        return Ok(());
+    }
+
+    let dir = files::backups_dir()?;
+    files::create_private_dir(&dir)?;
+
+    // Backups of the same second are numbered after the newest of them, as
+    // pruning may have removed the first ones.
+    let ts = timestamp();
+    let backups = list(name)?;
+    let path = match backups.iter().find(|b| b.timestamp.starts_with(&ts)) {
+        Some(newest) => {
+            let seq = sequence(&newest.timestamp) + 1;
+            dir.join(format!("{}-{}.{}{}", name, ts, seq, VAULT_EXT))
+        }
+        None => dir.join(format!("{}-{}{}", name, ts, VAULT_EXT)),
+    };
+
+    let data = std::fs::read(&current).context("Failed to read vault")?;
+    files::write_atomic(&path, &data).context("Failed to write backup")?;
+
+    for old in list(name)?.iter().skip(keep) {
+        std::fs::remove_file(&old.path)?;
+    }
+    Ok(())
+}
+
+/// Replace the vault `name` with `backup`, backing up the current vault
+/// first so the restore can itself be undone.
+pub fn restore(name: &str, backup: &Backup, keep: usize) -> Result<()> {
+    let data = std::fs::read(&backup.path).context("Failed to read backup")?;
+    let lv: LockedVault = serde_json::from_slice(&data).context("Backup is not a vault")?;
+    if lv.name != name {
+        return Err(anyhow!("Backup belongs to vault '{}'", lv.name));
+    }
+
+    create(name, keep)?;
+    let path = files::rpwd_path(&format!("{}{}", name, VAULT_EXT))?;
+    files::write_atomic(&path, &data).context("Failed to restore vault")
+}
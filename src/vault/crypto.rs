@@ -1,4 +1,135 @@
-use anyhow::{Error, Result};
+use crate::secret::SecretBytes;
+use anyhow::{anyhow, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+const KEY_LEN: u32 = 32;
+const TUNE_MIN_MEM: u32 = 8192;
+/// Bounds of the parameters, as vault headers are not trusted before the
+/// key derived from them is verified.
+const MAX_MEM_COST: u32 = 1024 * 1024;
+const MAX_TIME_COST: u32 = 64;
+const MAX_LANES: u32 = 16;
+
+/// Argon2 parameters used to derive a vault key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Kdf {
+    pub variant: String,
+    /// Memory cost in KiB.
+    pub mem_cost: u32,
+    pub time_cost: u32,
+    pub lanes: u32,
+}
+
+impl Kdf {
+    /// Parameters of vaults created before they were stored in the vault.
+    pub fn legacy() -> Self {
+        let config = argon2::Config::default();
+
+        Kdf {
+            variant: config.variant.as_lowercase_str().to_string(),
+            mem_cost: config.mem_cost,
+            time_cost: config.time_cost,
+            lanes: config.lanes,
+        }
+    }
+
+    /// Fail for parameters beyond the bounds above.
+    pub fn check(&self) -> Result<()> {
+        if self.mem_cost > MAX_MEM_COST || self.time_cost > MAX_TIME_COST || self.lanes > MAX_LANES
+        {
+            return Err(anyhow!(
+                "Invalid key derivation parameters {}, at most m={},t={},p={} are supported",
+                self,
+                MAX_MEM_COST,
+                MAX_TIME_COST,
+                MAX_LANES
+            ));
+        }
+        Ok(())
+    }
+
+    fn config(&self) -> Result<argon2::Config<'static>> {
+        self.check()?;
+        let variant = argon2::Variant::from_str(&self.variant)
+            .map_err(|_| anyhow!("Unknown argon2 variant '{}'", self.variant))?;
+        let thread_mode = if self.lanes > 1 {
+            argon2::ThreadMode::Parallel
+        } else {
+            argon2::ThreadMode::Sequential
+        };
+
+        Ok(argon2::Config {
+            variant,
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            thread_mode,
+            hash_length: KEY_LEN,
+            ..argon2::Config::default()
+        })
+    }
+
+    fn bench(&self) -> Result<Duration> {
+        let salt = [0; 32];
+        let start = Instant::now();
+        key(b"benchmark", &[], &salt, self)?;
+        Ok(start.elapsed())
+    }
+
+    /// Benchmark the host and pick argon2id parameters which take roughly
+    /// `target` to derive a key.
+    pub fn tune(target: Duration) -> Result<Self> {
+        let lanes = std::thread::available_parallelism().map_or(1, |n| n.get().min(4) as u32);
+        let mut kdf = Kdf {
+            variant: String::from("argon2id"),
+            mem_cost: TUNE_MIN_MEM,
+            time_cost: 3,
+            lanes,
+        };
+
+        loop {
+            let elapsed = kdf.bench()?;
+            if elapsed >= target {
+                let scaled = kdf.mem_cost as f64 * target.as_secs_f64() / elapsed.as_secs_f64();
+                kdf.mem_cost = (scaled as u32).clamp(TUNE_MIN_MEM, MAX_MEM_COST);
+                return Ok(kdf);
+            }
+
+            if kdf.mem_cost < MAX_MEM_COST {
+                kdf.mem_cost *= 2;
+            } else if kdf.time_cost < MAX_TIME_COST {
+                kdf.time_cost += 1;
+            } else {
+                return Ok(kdf);
+            }
+        }
+    }
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Kdf {
+            variant: String::from("argon2id"),
+            mem_cost: 65536,
+            time_cost: 3,
+            lanes: 4,
+        }
+    }
+}
+
+impl fmt::Display for Kdf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}$m={},t={},p={}",
+            self.variant, self.mem_cost, self.time_cost, self.lanes
+        )
+    }
+}
 
 pub fn salt(buf: &mut [u8]) {
     openssl::rand::rand_bytes(buf).unwrap();
@@ -8,8 +139,23 @@ pub fn rand_bytes(buf: &mut [u8]) {
     openssl::rand::rand_bytes(buf).unwrap();
 }
 
-pub fn key(pass: &[u8], salt: &[u8]) -> Result<Vec<u8>, Error> {
-    let config = argon2::Config::default();
+/// Derive a key from `pass`, keyed by `secret` such as a keyfile digest.
+/// An empty secret derives the same key as argon2 without one.
+pub fn key(pass: &[u8], secret: &[u8], salt: &[u8], kdf: &Kdf) -> Result<SecretBytes, Error> {
+    let mut config = kdf.config()?;
+    config.secret = secret;
+
+    Ok(SecretBytes::new(argon2::hash_raw(pass, salt, &config)?))
+}
 
-    Ok(argon2::hash_raw(pass, salt, &config)?)
+/// Digest of the contents of a keyfile.
+pub fn keyfile(path: &Path) -> Result<SecretBytes> {
+    let data = Zeroizing::new(
+        std::fs::read(path)
+            .with_context(|| format!("Failed to read keyfile {}", path.display()))?,
+    );
+    if data.is_empty() {
+        return Err(anyhow!("Keyfile {} is empty", path.display()));
+    }
+    Ok(SecretBytes::new(openssl::sha::sha256(&data).to_vec()))
 }
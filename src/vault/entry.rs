@@ -0,0 +1,97 @@
+use crate::otp::Totp;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroize;
+
+/// A single stored credential.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Entry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(alias = "pw")]
+    pub password: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otp: Option<Totp>,
+    /// Seconds since the unix epoch, 0 when unknown.
+    #[serde(default)]
+    pub created: u64,
+    #[serde(default)]
+    pub modified: u64,
+}
+
+/// Entries as stored in a vault, older vaults only hold the password.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Plain(String),
+    Full(Entry),
+}
+
+impl From<Stored> for Entry {
+    fn from(s: Stored) -> Self {
+        match s {
+            Stored::Plain(password) => {
+                let mut e = Entry::default();
+                e.password = password;
+                e
+            }
+            Stored::Full(e) => e,
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+impl Entry {
+    pub fn new(password: String) -> Self {
+        let mut e = Entry::default();
+        e.password = password;
+        e.created = now();
+        e.modified = e.created;
+        e
+    }
+
+    pub fn touch(&mut self) {
+        self.modified = now();
+    }
+
+    /// Look up a field by name, the well known fields take precedence over
+    /// custom fields of the same name.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "password" => Some(&self.password),
+            "username" => self.username.as_deref(),
+            "url" => self.urls.first().map(String::as_str),
+            "notes" => self.notes.as_deref(),
+            _ => self.fields.get(name).map(String::as_str),
+        }
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        self.username.zeroize();
+        self.password.zeroize();
+        self.notes.zeroize();
+        self.fields.values_mut().for_each(Zeroize::zeroize);
+    }
+}
+
+pub fn parse_entries(json: &str) -> serde_json::Result<HashMap<String, Entry>> {
+    let stored: HashMap<String, Stored> = serde_json::from_str(json)?;
+
+    Ok(stored.into_iter().map(|(k, v)| (k, v.into())).collect())
+}
@@ -1,119 +1,301 @@
+pub mod backup;
 mod crypto;
+mod entry;
 use crate::cli;
+use crate::error::Kind;
 use crate::files;
+use crate::files::FileLock;
+use crate::secret::SecretBytes;
 use anyhow::{anyhow, Context, Result};
+pub use crypto::{keyfile, Kdf};
+pub use entry::Entry;
 use openssl::base64::decode_block;
 use openssl::base64::encode_block;
-use openssl::symm::{decrypt, encrypt, Cipher};
+use openssl::symm::{decrypt, decrypt_aead, encrypt_aead, Cipher};
 use serde::{Deserialize, Serialize};
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::hash_map;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::option::Option;
 use std::path::Path;
 use std::str::FromStr;
 use std::string::String;
+use zeroize::Zeroizing;
 
 const SALT_LEN: usize = 256;
-const IV_LEN: usize = 16;
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
 const VAULT_EXT: &str = ".vlt";
 
+/// Vault format version written by `UnlockedVault::lock`.
+///
+/// 0: AES-256-CBC without authentication.
+/// 1: AES-256-GCM with the vault header bound as associated data.
+pub const VERSION: u32 = 1;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Password {
     pub id: String,
-    pub pw: String,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+/// What the key of a vault is derived from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Factors {
+    #[default]
+    Password,
+    /// The password along with a keyfile.
+    Both,
+    Keyfile,
+}
+
+impl Factors {
+    pub fn password(&self) -> bool {
+        *self != Factors::Keyfile
+    }
+
+    pub fn keyfile(&self) -> bool {
+        *self != Factors::Password
+    }
+
+    fn is_password(&self) -> bool {
+        *self == Factors::Password
+    }
+}
+
+impl fmt::Display for Factors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Factors::Password => "password",
+            Factors::Both => "both",
+            Factors::Keyfile => "keyfile",
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LockedVault {
     pub name: String,
+    #[serde(default)]
+    pub version: u32,
     pub iv: String,
     pub salt: String,
+    #[serde(default = "Kdf::legacy")]
+    pub kdf: Kdf,
+    #[serde(default, skip_serializing_if = "Factors::is_password")]
+    pub factors: Factors,
+    #[serde(default)]
+    pub tag: String,
     pub enc: String,
 }
 
+#[derive(Clone)]
 pub struct UnlockedVault {
     pub name: String,
     pub salt: Vec<u8>,
-    pub pws: HashMap<String, String>,
+    pub kdf: Kdf,
+    pub factors: Factors,
+    pub pws: HashMap<String, Entry>,
+    /// Revision of the stored vault this was unlocked from, `None` for new
+    /// vaults.
+    pub revision: Option<String>,
+}
+
+fn derive(
+    pass: &str,
+    keyfile: Option<&[u8]>,
+    salt: &[u8],
+    kdf: &Kdf,
+    factors: Factors,
+    name: &str,
+) -> Result<SecretBytes> {
+    let secret = match (factors.keyfile(), keyfile) {
+        (true, Some(k)) => k,
+        (true, None) => return Err(Kind::Auth.error(format!("Vault {} requires a keyfile", name))),
+        (false, _) => &[],
+    };
+    let pass = if factors.password() { pass } else { "" };
+    crypto::key(pass.as_bytes(), secret, salt, kdf)
 }
 
 impl LockedVault {
+    /// Hold the advisory lock of vault `name`, to keep other processes from
+    /// saving it during a read-modify-write cycle.
+    pub fn acquire(name: &str) -> Result<FileLock> {
+        files::create_private_dir(&files::rpwd()?)?;
+        FileLock::exclusive(&files::rpwd_path(&format!("{}{}.lock", name, VAULT_EXT))?)
+    }
+
+    /// Identifies this particular write of the vault, as every lock picks a
+    /// fresh iv.
+    pub fn revision(&self) -> &str {
+        &self.iv
+    }
+
+    /// Derive the key of the vault from its password.
+    pub fn key(&self, pass: &str) -> Result<SecretBytes> {
+        self.key_with_keyfile(pass, None)
+    }
+
+    /// Derive the key of the vault from its password and the digest of its
+    /// keyfile, as returned by `keyfile`.
+    pub fn key_with_keyfile(&self, pass: &str, keyfile: Option<&[u8]>) -> Result<SecretBytes> {
+        let salt = decode_block(&self.salt).context("Failed to decode salt")?;
+        derive(pass, keyfile, &salt, &self.kdf, self.factors, &self.name)
+    }
+
     pub fn unlock(&self, pass: &str) -> Result<UnlockedVault> {
+        self.unlock_with_key(&self.key(pass)?)
+    }
+
+    pub fn unlock_with_key(&self, key: &[u8]) -> Result<UnlockedVault> {
         let salt = decode_block(&self.salt).context("Failed to decode salt")?;
         let data = decode_block(&self.enc).context("Failed to decode data")?;
         let iv = decode_block(&self.iv).context("Failed to decode iv")?;
-        let key = crypto::key(pass.as_bytes(), &salt)?;
-        let cipher = Cipher::aes_256_cbc();
-        let plain =
-            decrypt(cipher, &key, Some(&iv), &data).context("Cipher could not be decrypted")?;
+        let plain = Zeroizing::new(match self.version {
+            0 => decrypt(Cipher::aes_256_cbc(), key, Some(&iv), &data)
+                .map_err(|_| Kind::Auth.error("Cipher could not be decrypted"))?,
+            1 => {
+                let tag = decode_block(&self.tag).context("Failed to decode tag")?;
+                decrypt_aead(
+                    Cipher::aes_256_gcm(),
+                    key,
+                    Some(&iv),
+                    &self.aad(),
+                    &data,
+                    &tag,
+                )
+                .map_err(|_| {
+                    Kind::Auth
+                        .error("Cipher could not be decrypted, wrong password or tampered vault")
+                })?
+            }
+            v => return Err(anyhow!("Unsupported vault version {}", v)),
+        });
 
-        let json = String::from_utf8(plain).context("UTF8 conversion failed")?;
-        let passwords: HashMap<String, String> =
-            serde_json::from_str(&json).context("JSON conversion failed")?;
+        let json = std::str::from_utf8(&plain).context("UTF8 conversion failed")?;
+        let passwords = entry::parse_entries(json).context("JSON conversion failed")?;
 
         Ok(UnlockedVault {
             name: self.name.clone(),
             salt,
+            kdf: self.kdf.clone(),
+            factors: self.factors,
             pws: passwords,
+            revision: Some(self.revision().to_string()),
         })
     }
 
+    /// Associated data authenticated along with the ciphertext, covering
+    /// every header field which influences how the vault is decrypted. The
+    /// layout is fixed, integers being big-endian and strings prefixed by
+    /// their length in bytes as u32:
+    ///
+    /// version u32, name, salt as stored in base64, KDF variant, mem_cost
+    /// u32, time_cost u32, lanes u32, factors u8 (0 password, 1 both,
+    /// 2 keyfile).
+    pub fn aad(&self) -> Vec<u8> {
+        fn string(aad: &mut Vec<u8>, s: &str) {
+            aad.extend_from_slice(&(s.len() as u32).to_be_bytes());
+            aad.extend_from_slice(s.as_bytes());
+        }
+
+        let mut aad = Vec::new();
+        aad.extend_from_slice(&self.version.to_be_bytes());
+        string(&mut aad, &self.name);
+        string(&mut aad, &self.salt);
+        string(&mut aad, &self.kdf.variant);
+        aad.extend_from_slice(&self.kdf.mem_cost.to_be_bytes());
+        aad.extend_from_slice(&self.kdf.time_cost.to_be_bytes());
+        aad.extend_from_slice(&self.kdf.lanes.to_be_bytes());
+        aad.push(match self.factors {
+            Factors::Password => 0,
+            Factors::Both => 1,
+            Factors::Keyfile => 2,
+        });
+        aad
+    }
+
     pub fn exists(&self) -> bool {
-        let path = files::rpwd_path(&format!("{}{}", self.name, VAULT_EXT));
-        path.exists()
+        files::rpwd_path(&format!("{}{}", self.name, VAULT_EXT)).is_ok_and(|p| p.exists())
+    }
+
+    /// Save the vault, first keeping the current file as one of at most
+    /// `keep` backups.
+    pub fn save_with_backup(&self, keep: usize) -> Result<()> {
+        backup::create(&self.name, keep)?;
+        self.save()
     }
 
     pub fn save(&self) -> Result<()> {
-        let path = files::rpwd_path(&format!("{}{}", self.name, VAULT_EXT));
+        files::create_private_dir(&files::rpwd()?)?;
+        let path = files::rpwd_path(&format!("{}{}", self.name, VAULT_EXT))?;
         let json = serde_json::to_string(&self).context("Failed to serialize passwords")?;
 
-        File::create(&path)
-            .and_then(|mut f| f.write_all(json.as_bytes()))
-            .context("Failed to save vault")
+        files::write_atomic(&path, json.as_bytes()).context("Failed to save vault")
     }
 
     pub fn delete(&self) -> Result<()> {
         if cli::yesorno(
             format!("Would you really like to delete the vault {}?", &self.name).as_str(),
-        ) && cli::yesorno("Are you reaaaaally sure? It's permanent.")
+        )? && cli::yesorno("Are you reaaaaally sure? It's permanent.")?
         {
             files::delete(format!("{}{}", &self.name, VAULT_EXT).as_str())?;
             return Ok(());
         }
-        return Err(anyhow!("Did not delete vault"));
+        Err(Kind::Declined.error("Did not delete vault"))
     }
 }
 
 impl FromStr for LockedVault {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let fname = files::rpwd_path(&format!("{}{}", s, VAULT_EXT));
-        let f = File::open(&fname)?;
+        let fname = files::rpwd_path(&format!("{}{}", s, VAULT_EXT))?;
+        let f = File::open(&fname).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Kind::NotFound.error(format!("No vault named {}", s)),
+            _ => e.into(),
+        })?;
         Ok(serde_json::from_reader::<File, LockedVault>(f)?)
     }
 }
 
 impl UnlockedVault {
     pub fn new(vault: &str) -> UnlockedVault {
+        UnlockedVault::with_kdf(vault, Kdf::default())
+    }
+
+    pub fn with_kdf(vault: &str, kdf: Kdf) -> UnlockedVault {
         let mut salt = [0; SALT_LEN];
         crypto::salt(&mut salt);
 
         UnlockedVault {
             name: vault.to_string(),
             salt: salt.to_vec(),
+            kdf,
+            factors: Factors::Password,
             pws: HashMap::new(),
+            revision: None,
         }
     }
 
+    /// Generate a fresh salt, so the next `lock` derives an unrelated key.
+    pub fn rekey(&mut self) {
+        let mut salt = [0; SALT_LEN];
+        crypto::salt(&mut salt);
+        self.salt = salt.to_vec();
+    }
+
     pub fn import(&mut self, path: &Path) -> Result<Vec<Password>> {
-        let f = File::open(&path)?;
+        let f = File::open(path)?;
         let pws: Vec<Password> = serde_json::from_reader::<File, Vec<Password>>(f)?;
 
         let dup = pws
             .iter()
-            .filter(|p| !self.try_insert(p.id.clone(), p.pw.clone()))
+            .filter(|p| !self.try_insert(p.id.clone(), p.entry.clone()))
             .cloned()
             .collect();
 
@@ -126,48 +308,125 @@ impl UnlockedVault {
             .iter()
             .map(|(k, v)| Password {
                 id: k.to_string(),
-                pw: v.to_string(),
+                entry: v.clone(),
             })
             .collect();
         let json = serde_json::to_string_pretty(&pws).context("Failed to serialize vault")?;
-        File::create(&path).and_then(|mut f| f.write_all(json.as_bytes()))?;
+        files::create_private(path)?.write_all(json.as_bytes())?;
         Ok(())
     }
 
+    /// Derive the key of the vault from its password.
+    pub fn key(&self, pass: &str) -> Result<SecretBytes> {
+        self.key_with_keyfile(pass, None)
+    }
+
+    /// Derive the key of the vault from its password and the digest of its
+    /// keyfile, as returned by `keyfile`.
+    pub fn key_with_keyfile(&self, pass: &str, keyfile: Option<&[u8]>) -> Result<SecretBytes> {
+        derive(
+            pass,
+            keyfile,
+            &self.salt,
+            &self.kdf,
+            self.factors,
+            &self.name,
+        )
+        .context("Failed to derive key")
+    }
+
     pub fn lock(&self, pass: &str) -> Result<LockedVault> {
-        let cipher = Cipher::aes_256_cbc();
-        let salt = &self.salt;
-        let key = crypto::key(pass.as_bytes(), salt).context("Failed to derive key")?;
-        let data =
-            serde_json::to_string_pretty(&self.pws).context("Failed to serialize passwords")?;
-        let key = key;
+        self.lock_with_key(&self.key(pass)?)
+    }
+
+    pub fn lock_with_key(&self, key: &[u8]) -> Result<LockedVault> {
+        let cipher = Cipher::aes_256_gcm();
+        let data = Zeroizing::new(
+            serde_json::to_string_pretty(&self.pws).context("Failed to serialize passwords")?,
+        );
 
         let mut iv = [0; IV_LEN];
         crypto::rand_bytes(&mut iv);
-        let ciphertext = encrypt(cipher, &key, Some(&iv), data.as_bytes())
-            .context("Failed to encrypt plaintext")?;
-        Ok(LockedVault {
+        let mut lv = LockedVault {
             name: self.name.clone(),
-            iv: encode_block(&iv.to_vec()),
-            salt: encode_block(&self.salt.to_vec()),
-            enc: encode_block(&ciphertext),
-        })
+            version: VERSION,
+            iv: encode_block(&iv),
+            salt: encode_block(&self.salt),
+            kdf: self.kdf.clone(),
+            factors: self.factors,
+            tag: String::new(),
+            enc: String::new(),
+        };
+
+        let mut tag = [0; TAG_LEN];
+        let ciphertext = encrypt_aead(cipher, key, Some(&iv), &lv.aad(), data.as_bytes(), &mut tag)
+            .context("Failed to encrypt plaintext")?;
+        lv.tag = encode_block(&tag);
+        lv.enc = encode_block(&ciphertext);
+        Ok(lv)
+    }
+
+    /// Whether `lv` is the stored revision this vault was unlocked from.
+    pub fn is_revision_of(&self, lv: &LockedVault) -> bool {
+        self.revision.as_deref() == Some(lv.revision())
     }
 
-    pub fn try_insert(&mut self, id: String, password: String) -> bool {
-        if let Entry::Vacant(e) = self.pws.entry(id) {
-            e.insert(password);
+    /// Apply the changes from `base` to `theirs` onto this vault, which
+    /// also derives from `base`. Entries changed differently on both sides
+    /// are left untouched and returned.
+    pub fn merge(&mut self, base: &UnlockedVault, theirs: &UnlockedVault) -> Vec<String> {
+        let ids: BTreeSet<String> = base
+            .pws
+            .keys()
+            .chain(self.pws.keys())
+            .chain(theirs.pws.keys())
+            .cloned()
+            .collect();
+
+        let mut conflicts = Vec::new();
+        for id in ids {
+            let old = base.pws.get(&id);
+            let ours = self.pws.get(&id);
+            let new = theirs.pws.get(&id);
+            if ours == new || new == old {
+                continue;
+            }
+            if ours != old {
+                conflicts.push(id);
+                continue;
+            }
+            match new {
+                Some(e) => self.insert(id, e.clone()),
+                None => {
+                    self.pws.remove(&id);
+                }
+            }
+        }
+        conflicts
+    }
+
+    pub fn try_insert(&mut self, id: String, entry: Entry) -> bool {
+        if let hash_map::Entry::Vacant(e) = self.pws.entry(id) {
+            e.insert(entry);
             return true;
         }
 
         false
     }
 
-    pub fn insert(&mut self, id: String, password: String) {
-        self.pws.insert(id, password);
+    pub fn insert(&mut self, id: String, entry: Entry) {
+        self.pws.insert(id, entry);
     }
 
-    pub fn get(&self, id: String) -> Option<&String> {
+    pub fn remove(&mut self, id: String) -> Option<Entry> {
+        self.pws.remove(&id)
+    }
+
+    pub fn get(&self, id: String) -> Option<&Entry> {
         self.pws.get(&id)
     }
+
+    pub fn get_mut(&mut self, id: String) -> Option<&mut Entry> {
+        self.pws.get_mut(&id)
+    }
 }
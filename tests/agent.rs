@@ -0,0 +1,122 @@
+mod common;
+
+use rlib::agent::{self, Request, Response};
+use rlib::vault::{Entry, UnlockedVault};
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn serve_requests() {
+    let home = common::home("agent");
+    let mut uv = UnlockedVault::new("agent");
+    uv.insert(String::from("pw"), Entry::new(String::from("stored")));
+    let key = uv.key("password").unwrap();
+    uv.lock_with_key(&key).unwrap().save().unwrap();
+
+    let sock = home.path.join("agent.sock");
+    let listener = agent::bind(&sock).unwrap();
+    thread::spawn(move || agent::serve(listener, Duration::from_secs(60), 0));
+
+    // A client which never sends its request does not hold up others.
+    let _idle = UnixStream::connect(&sock).unwrap();
+
+    let get = || Request::Get {
+        vault: String::from("agent"),
+        alias: String::from("pw"),
+    };
+    assert!(matches!(
+        agent::request(&sock, &get()),
+        Ok(Response::Locked)
+    ));
+    agent::request(&sock, &Request::unlock("agent", &key)).unwrap();
+    match agent::request(&sock, &get()).unwrap() {
+        Response::Entry { entry: Some(e) } => assert_eq!(e.password, "stored"),
+        _ => panic!("Expected the entry"),
+    }
+
+    let add = Request::Add {
+        vault: String::from("agent"),
+        alias: String::from("new"),
+        entry: Entry::new(String::from("added")),
+    };
+    agent::request(&sock, &add).unwrap();
+    let list = Request::List {
+        vault: String::from("agent"),
+    };
+    match agent::request(&sock, &list).unwrap() {
+        Response::List { entries } => {
+            assert_eq!(entries.len(), 2);
+            assert!(entries.iter().all(|(_, e)| e.password.is_empty()));
+        }
+        _ => panic!("Expected the entries"),
+    }
+
+    let remove = |alias: &str| Request::Remove {
+        vault: String::from("agent"),
+        alias: alias.to_string(),
+    };
+    agent::request(&sock, &remove("new")).unwrap();
+    agent::request(&sock, &remove("missing")).unwrap();
+    match agent::request(&sock, &list).unwrap() {
+        Response::List { entries } => {
+            let aliases: Vec<&str> = entries.iter().map(|(a, _)| a.as_str()).collect();
+            assert_eq!(aliases, ["pw"]);
+        }
+        _ => panic!("Expected the entries"),
+    }
+    // The removal was saved to the vault.
+    let lv: rlib::vault::LockedVault = "agent".parse().unwrap();
+    assert!(lv
+        .unlock_with_key(&key)
+        .unwrap()
+        .get(String::from("new"))
+        .is_none());
+}
+
+#[test]
+fn rekeyed_vault() {
+    let home = common::home("agent-rekey");
+    let mut uv = UnlockedVault::new("rekeyed");
+    uv.insert(String::from("pw"), Entry::new(String::from("stored")));
+    let key = uv.key("password").unwrap();
+    uv.lock_with_key(&key).unwrap().save().unwrap();
+
+    let sock = home.path.join("agent.sock");
+    let listener = agent::bind(&sock).unwrap();
+    thread::spawn(move || agent::serve(listener, Duration::from_secs(60), 0));
+    agent::request(&sock, &Request::unlock("rekeyed", &key)).unwrap();
+
+    // Changing the password drops the stale key, for the client to unlock
+    // the vault again.
+    let new_key = uv.key("changed").unwrap();
+    uv.lock_with_key(&new_key).unwrap().save().unwrap();
+    let get = || Request::Get {
+        vault: String::from("rekeyed"),
+        alias: String::from("pw"),
+    };
+    assert!(matches!(
+        agent::request(&sock, &get()),
+        Ok(Response::Locked)
+    ));
+    agent::request(&sock, &Request::unlock("rekeyed", &new_key)).unwrap();
+    assert!(matches!(
+        agent::request(&sock, &get()),
+        Ok(Response::Entry { entry: Some(_) })
+    ));
+}
+
+#[test]
+fn bind_only_replaces_sockets() {
+    let home = common::home("agent-bind");
+    let path = home.path.join("notes.txt");
+    std::fs::write(&path, "notes").unwrap();
+    assert!(agent::bind(&path).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "notes");
+
+    // The socket of an agent which is gone is replaced.
+    let sock = home.path.join("agent.sock");
+    drop(agent::bind(&sock).unwrap());
+    assert!(sock.exists());
+    agent::bind(&sock).unwrap();
+}
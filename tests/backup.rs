@@ -0,0 +1,75 @@
+mod common;
+
+use rlib::vault::backup::{self, timestamp_at};
+use rlib::vault::{Entry, Kdf, LockedVault, UnlockedVault};
+
+#[test]
+fn timestamps() {
+    assert_eq!(timestamp_at(0), "19700101T000000Z");
+    assert_eq!(timestamp_at(946684799), "19991231T235959Z");
+    assert_eq!(timestamp_at(951827696), "20000229T123456Z");
+    assert_eq!(timestamp_at(1609459199), "20201231T235959Z");
+    assert_eq!(timestamp_at(1609459200), "20210101T000000Z");
+    assert_eq!(timestamp_at(1709164800), "20240229T000000Z");
+    assert_eq!(timestamp_at(4107542399), "21000228T235959Z");
+    assert_eq!(timestamp_at(4107542400), "21000301T000000Z");
+}
+
+fn revision(lv: &LockedVault, key: &[u8]) -> String {
+    let uv = lv.unlock_with_key(key).unwrap();
+    uv.get(String::from("rev")).unwrap().password.clone()
+}
+
+#[test]
+fn list_prune_restore() {
+    let home = common::home("backup");
+
+    let kdf = Kdf {
+        variant: String::from("argon2id"),
+        mem_cost: 1024,
+        time_cost: 1,
+        lanes: 1,
+    };
+    let mut uv = UnlockedVault::with_kdf("backed", kdf);
+    let key = uv.key("password").unwrap();
+    assert!(backup::list("backed").unwrap().is_empty());
+
+    let keep = 3;
+    for rev in 0..6 {
+        uv.insert(String::from("rev"), Entry::new(rev.to_string()));
+        uv.lock_with_key(&key)
+            .unwrap()
+            .save_with_backup(keep)
+            .unwrap();
+    }
+
+    // Revisions 2 to 4 are kept, newest first, while 5 is the vault itself.
+    let backups = backup::list("backed").unwrap();
+    let revs: Vec<String> = backups
+        .iter()
+        .map(|b| {
+            let lv: LockedVault = serde_json::from_slice(&std::fs::read(&b.path).unwrap()).unwrap();
+            revision(&lv, &key)
+        })
+        .collect();
+    assert_eq!(revs, ["4", "3", "2"]);
+
+    backup::restore("backed", &backups[2], keep).unwrap();
+    let lv: LockedVault = "backed".parse().unwrap();
+    assert_eq!(revision(&lv, &key), "2");
+    let backups = backup::list("backed").unwrap();
+    assert_eq!(backups.len(), keep);
+    let newest: LockedVault =
+        serde_json::from_slice(&std::fs::read(&backups[0].path).unwrap()).unwrap();
+    assert_eq!(revision(&newest, &key), "5");
+
+    let mut other = UnlockedVault::new("other").lock("password").unwrap();
+    other.name = String::from("other");
+    let path = home.path.join("other.vlt");
+    std::fs::write(&path, serde_json::to_string(&other).unwrap()).unwrap();
+    let foreign = backup::Backup {
+        path,
+        timestamp: timestamp_at(0),
+    };
+    assert!(backup::restore("backed", &foreign, keep).is_err());
+}
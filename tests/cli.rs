@@ -0,0 +1,60 @@
+use rlib::cli;
+use std::fs;
+use std::os::unix::io::AsRawFd;
+
+#[test]
+fn read_password() {
+    let dir = "/tmp/rpwtest/cli";
+    fs::create_dir_all(dir).unwrap();
+    let file = format!("{}/pass", dir);
+    fs::write(&file, "hunter2\r\nignored\n").unwrap();
+
+    let app = cli::build();
+    let m =
+        app.clone()
+            .get_matches_from(vec!["rpw", "list", "-v", "demo", "--password-file", &file]);
+    let args = m.subcommand_matches("list").unwrap();
+    assert!(cli::password_given(args));
+    let pass = cli::read_password(args, "demo").unwrap().unwrap();
+    assert_eq!(pass.as_str(), "hunter2");
+
+    let m = app.get_matches_from(vec!["rpw", "list", "-v", "demo"]);
+    let args = m.subcommand_matches("list").unwrap();
+    assert!(cli::read_password(args, "demo").unwrap().is_none());
+    assert!(!cli::password_given(args));
+
+    cli::set_password_command(Some(String::from("echo \"pw-of-$RPW_VAULT\"")));
+    let pass = cli::read_password(args, "demo").unwrap().unwrap();
+    assert_eq!(pass.as_str(), "pw-of-demo");
+    // The password command only knows the passwords of existing vaults.
+    assert!(cli::given_password(args).unwrap().is_none());
+
+    cli::set_password_command(Some(String::from("false")));
+    assert!(cli::read_password(args, "demo").is_err());
+    cli::set_password_command(None);
+}
+
+#[test]
+fn password_fd() {
+    let dir = "/tmp/rpwtest/cli";
+    fs::create_dir_all(dir).unwrap();
+    let file = format!("{}/fdpass", dir);
+    fs::write(&file, "hunter3\n").unwrap();
+    let f = fs::File::open(&file).unwrap();
+    let fd = f.as_raw_fd().to_string();
+
+    let read = |fd: &str| {
+        let m =
+            cli::build().get_matches_from(vec!["rpw", "list", "-v", "demo", "--password-fd", fd]);
+        let args = m.subcommand_matches("list").unwrap();
+        cli::given_password(args)
+    };
+    assert_eq!(read(&fd).unwrap().unwrap().as_str(), "hunter3");
+    // The descriptor belongs to the caller and stays open.
+    assert!(unsafe { libc::fcntl(f.as_raw_fd(), libc::F_GETFD) } != -1);
+
+    for fd in &["0", "1", "2", "x", "100000"] {
+        let err = read(fd).err().unwrap();
+        assert_eq!(rlib::error::Kind::of(&err), rlib::error::Kind::Usage);
+    }
+}
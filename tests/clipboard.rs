@@ -0,0 +1,160 @@
+mod common;
+
+use anyhow::{anyhow, Result};
+use common::MemoryStore;
+use rlib::clipboard::{self, Backend, Clipboard, Copied};
+use std::cell::RefCell;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+#[test]
+fn osc52() {
+    assert_eq!(clipboard::osc52("tok", false), "\x1b]52;c;dG9r\x07");
+    assert_eq!(
+        clipboard::osc52("tok", true),
+        "\x1bPtmux;\x1b\x1b]52;c;dG9r\x07\x1b\\"
+    );
+}
+
+#[test]
+fn backend_names() {
+    let names = r#"["auto", "pbcopy", "wl-copy", "xclip", "xsel", "tmux", "osc52"]"#;
+    let backends: Vec<Backend> = serde_json::from_str(names).unwrap();
+    assert_eq!(backends[2], Backend::WlCopy);
+    assert_eq!(backends[6], Backend::Osc52);
+    assert_eq!(Backend::Xsel.resolve().unwrap(), Backend::Xsel);
+}
+
+#[test]
+fn mac() {
+    // Test case 2 of RFC 4231.
+    let mac = clipboard::mac(b"Jefe", "what do ya want for nothing?").unwrap();
+    let hex: String = mac.iter().map(|b| format!("{:02x}", b)).collect();
+    assert_eq!(
+        hex,
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+/// A clipboard in memory, which can be made unreadable like OSC 52.
+#[derive(Default)]
+struct FakeClipboard {
+    text: RefCell<Option<String>>,
+    unreadable: bool,
+}
+
+impl FakeClipboard {
+    fn holding(text: &str) -> Self {
+        FakeClipboard {
+            text: RefCell::new(Some(text.to_string())),
+            unreadable: false,
+        }
+    }
+
+    fn text(&self) -> Option<String> {
+        self.text.borrow().clone()
+    }
+}
+
+impl Clipboard for FakeClipboard {
+    fn copy(&self, s: &str) -> Result<()> {
+        *self.text.borrow_mut() = Some(s.to_string());
+        Ok(())
+    }
+
+    fn paste(&self) -> Result<Option<Zeroizing<String>>> {
+        if self.unreadable {
+            return Err(anyhow!("unreadable"));
+        }
+        Ok(self.text().map(Zeroizing::new))
+    }
+
+    fn clear(&self) -> Result<()> {
+        *self.text.borrow_mut() = None;
+        Ok(())
+    }
+}
+
+const TTL: Duration = Duration::from_secs(10);
+
+#[test]
+fn restore_previous() {
+    let clip = FakeClipboard::holding("mine");
+    let marks = MemoryStore::default();
+    let copied = Copied::copy(&clip, &marks, "secret", TTL).unwrap();
+    assert_eq!(clip.text().as_deref(), Some("secret"));
+    assert_eq!(copied.previous.as_deref(), Some("mine"));
+    // Neither the secret nor a plain digest of it is kept.
+    let mark = marks.entries.borrow().values().next().unwrap().0.clone();
+    assert!(!mark.windows(6).any(|w| w == b"secret"));
+
+    assert!(copied.clear(&clip, &marks).unwrap());
+    assert_eq!(clip.text().as_deref(), Some("mine"));
+    assert!(marks.entries.borrow().is_empty());
+
+    // Cleared twice, the secret is no longer there.
+    assert!(!copied.clear(&clip, &marks).unwrap());
+    assert_eq!(clip.text().as_deref(), Some("mine"));
+}
+
+#[test]
+fn leave_copied_since() {
+    let clip = FakeClipboard::default();
+    let marks = MemoryStore::default();
+    let copied = Copied::copy(&clip, &marks, "secret", TTL).unwrap();
+    assert!(copied.previous.is_none());
+
+    clip.copy("copied since").unwrap();
+    assert!(!copied.clear(&clip, &marks).unwrap());
+    assert_eq!(clip.text().as_deref(), Some("copied since"));
+}
+
+#[test]
+fn never_restore_earlier_secret() {
+    let clip = FakeClipboard::holding("mine");
+    let marks = MemoryStore::default();
+    let first = Copied::copy(&clip, &marks, "first", TTL).unwrap();
+    let second = Copied::copy(&clip, &marks, "second", TTL).unwrap();
+    assert!(second.previous.is_none());
+
+    // The first is no longer on the clipboard, the second is emptied.
+    assert!(!first.clear(&clip, &marks).unwrap());
+    assert_eq!(clip.text().as_deref(), Some("second"));
+    assert!(second.clear(&clip, &marks).unwrap());
+    assert!(clip.text().is_none());
+
+    // Once cleared, the same text copied by the user is restored again.
+    clip.copy("first").unwrap();
+    let third = Copied::copy(&clip, &marks, "third", TTL).unwrap();
+    assert_eq!(third.previous.as_deref(), Some("first"));
+}
+
+#[test]
+fn without_keyring() {
+    let clip = FakeClipboard::holding("mine");
+    let marks = MemoryStore::default();
+    marks.capacity.set(Some(0));
+    let first = Copied::copy(&clip, &marks, "first", TTL).unwrap();
+    assert!(first.previous.is_none());
+    let second = Copied::copy(&clip, &marks, "second", TTL).unwrap();
+    assert!(second.previous.is_none());
+
+    // The unmarked first secret is not brought back.
+    assert!(second.clear(&clip, &marks).unwrap());
+    assert!(clip.text().is_none());
+}
+
+#[test]
+fn clear_unreadable() {
+    let clip = FakeClipboard {
+        text: RefCell::new(Some(String::from("mine"))),
+        unreadable: true,
+    };
+    let marks = MemoryStore::default();
+    let copied = Copied::copy(&clip, &marks, "secret", TTL).unwrap();
+    assert!(copied.previous.is_none());
+
+    clip.copy("copied since").unwrap();
+    assert!(copied.clear(&clip, &marks).unwrap());
+    assert!(clip.text().is_none());
+}
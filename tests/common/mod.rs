@@ -0,0 +1,118 @@
+//! Helpers shared by the integration tests, each of which only uses some.
+#![allow(dead_code)]
+
+use anyhow::{anyhow, Result};
+use rlib::files;
+use rlib::keyring::Store;
+use rlib::secret::SecretBytes;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Guards the environment, which is shared by the tests of a binary running
+/// in parallel. Tests changing it or reading files through it hold a `Home`.
+static ENV: Mutex<()> = Mutex::new(());
+
+/// A temporary directory holding `RPW_HOME`, with the environment to itself
+/// until dropped. Variables set through it are restored then.
+pub struct Home {
+    /// Directory of the test, removed when it starts.
+    pub root: PathBuf,
+    /// `RPW_HOME`, `rpw` within `root`.
+    pub path: PathBuf,
+    saved: RefCell<Vec<(OsString, Option<OsString>)>>,
+    _env: MutexGuard<'static, ()>,
+}
+
+/// A fresh `Home` for the test `name`.
+pub fn home(name: &str) -> Home {
+    // A test failing while holding the lock leaves the environment as usual.
+    let env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let root = PathBuf::from(format!("/tmp/rpwtest/{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let path = root.join("rpw");
+    files::create_private_dir(&path).unwrap();
+    let home = Home {
+        root,
+        path,
+        saved: RefCell::new(Vec::new()),
+        _env: env,
+    };
+    home.set_var(files::HOME_VAR, &home.path);
+    home
+}
+
+impl Home {
+    pub fn set_var(&self, name: &str, value: impl AsRef<OsStr>) {
+        self.save(name);
+        std::env::set_var(name, value);
+    }
+
+    pub fn remove_var(&self, name: &str) {
+        self.save(name);
+        std::env::remove_var(name);
+    }
+
+    fn save(&self, name: &str) {
+        let mut saved = self.saved.borrow_mut();
+        if !saved.iter().any(|(n, _)| n == name) {
+            saved.push((name.into(), std::env::var_os(name)));
+        }
+    }
+}
+
+impl Drop for Home {
+    fn drop(&mut self) {
+        for (name, value) in self.saved.borrow_mut().drain(..).rev() {
+            match value {
+                Some(value) => std::env::set_var(name, value),
+                None => std::env::remove_var(name),
+            }
+        }
+    }
+}
+
+/// A keyring in memory with a clock of its own, failing to add entries
+/// beyond its `capacity`.
+#[derive(Default)]
+pub struct MemoryStore {
+    pub entries: RefCell<HashMap<String, (Vec<u8>, Duration)>>,
+    pub now: Cell<Duration>,
+    pub capacity: Cell<Option<usize>>,
+}
+
+impl MemoryStore {
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+impl Store for MemoryStore {
+    fn add(&self, desc: &str, payload: &[u8], ttl: Duration) -> Result<()> {
+        if self
+            .capacity
+            .get()
+            .is_some_and(|c| self.entries.borrow().len() >= c && self.read(desc).is_none())
+        {
+            return Err(anyhow!("keyring full"));
+        }
+        let expires = self.now.get() + ttl;
+        self.entries
+            .borrow_mut()
+            .insert(desc.to_string(), (payload.to_vec(), expires));
+        Ok(())
+    }
+
+    fn read(&self, desc: &str) -> Option<SecretBytes> {
+        let entries = self.entries.borrow();
+        let (payload, expires) = entries.get(desc)?;
+        (*expires > self.now.get()).then(|| SecretBytes::new(payload.clone()))
+    }
+
+    fn remove(&self, desc: &str) -> Result<bool> {
+        Ok(self.entries.borrow_mut().remove(desc).is_some())
+    }
+}
@@ -0,0 +1,24 @@
+mod common;
+
+use rlib::config::Config;
+use rlib::files;
+use std::fs;
+
+#[test]
+fn load_or_create() {
+    let _home = common::home("config");
+    let path = files::config_path().unwrap();
+    assert!(!path.exists());
+    let config = Config::load_or_create().unwrap();
+    assert_eq!(config.backups, Config::new().backups);
+    assert!(path.exists());
+
+    // A malformed configuration is reported, not replaced by the defaults.
+    fs::write(&path, r#"{"backups": 9,"#).unwrap();
+    let err = Config::load_or_create().err().unwrap();
+    assert!(format!("{:#}", err).contains(&path.display().to_string()));
+    assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"backups": 9,"#);
+
+    fs::write(&path, r#"{"backups": 9}"#).unwrap();
+    assert_eq!(Config::load_or_create().unwrap().backups, 9);
+}
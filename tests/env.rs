@@ -0,0 +1,49 @@
+use rlib::env::{self, Mapping};
+use rlib::vault::Entry;
+
+#[test]
+fn parse_mappings() {
+    let text = "# deploy\nDB_PASS=db\n\n  API_KEY = api/token\n";
+    let mappings = env::parse(text).unwrap();
+    assert_eq!(
+        mappings,
+        vec![
+            Mapping {
+                var: String::from("DB_PASS"),
+                secret: String::from("db"),
+            },
+            Mapping {
+                var: String::from("API_KEY"),
+                secret: String::from("api/token"),
+            },
+        ]
+    );
+
+    assert!(env::parse("DB_PASS").is_err());
+    assert!(env::parse("1DB=db").is_err());
+    assert!(env::parse("DB=").is_err());
+}
+
+#[test]
+fn resolve() {
+    let find = |alias: &str| -> anyhow::Result<Option<Entry>> {
+        let mut entry = Entry::new(format!("pw-of-{}", alias));
+        entry
+            .fields
+            .insert(String::from("token"), String::from("t0k"));
+        Ok(match alias {
+            "db" | "work/gh" => Some(entry),
+            _ => None,
+        })
+    };
+
+    assert_eq!(env::resolve("db", find).unwrap().as_str(), "pw-of-db");
+    assert_eq!(env::resolve("db/token", find).unwrap().as_str(), "t0k");
+    assert_eq!(
+        env::resolve("work/gh", find).unwrap().as_str(),
+        "pw-of-work/gh"
+    );
+    assert_eq!(env::resolve("work/gh/token", find).unwrap().as_str(), "t0k");
+    assert!(env::resolve("db/nope", find).is_err());
+    assert!(env::resolve("nope", find).is_err());
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use rlib::files;
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::PathBuf;
+
+fn mode(path: &PathBuf) -> u32 {
+    fs::symlink_metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+#[test]
+fn permission_issues() {
+    let env = common::home("files");
+    let (root, home) = (&env.root, &env.path);
+
+    let vault = home.join("loose.vlt");
+    fs::write(&vault, "{}").unwrap();
+    fs::set_permissions(&vault, fs::Permissions::from_mode(0o644)).unwrap();
+    let target = root.join("elsewhere");
+    fs::write(&target, "").unwrap();
+    fs::set_permissions(&target, fs::Permissions::from_mode(0o644)).unwrap();
+    symlink(&target, home.join("link.vlt")).unwrap();
+
+    let issues = files::permission_issues().unwrap();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].path, vault);
+    assert_eq!(issues[0].mode, 0o644);
+    issues[0].fix().unwrap();
+    assert_eq!(mode(&vault), 0o600);
+    assert_eq!(mode(&target), 0o644);
+    assert!(files::permission_issues().unwrap().is_empty());
+
+    // A home which is itself a symlink is checked where it leads.
+    let linked = root.join("linked");
+    symlink(home, &linked).unwrap();
+    env.set_var(files::HOME_VAR, &linked);
+    fs::set_permissions(&vault, fs::Permissions::from_mode(0o640)).unwrap();
+    let issues = files::permission_issues().unwrap();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].path, vault);
+}
@@ -0,0 +1,92 @@
+use rlib::generator::{PassphraseSpec, PasswordSpec, Wordlist};
+
+#[test]
+fn password_classes() {
+    let spec = PasswordSpec {
+        length: 12,
+        symbols: false,
+        min_symbols: 0,
+        min_digits: 4,
+        exclude_ambiguous: true,
+        ..PasswordSpec::default()
+    };
+
+    for _ in 0..100 {
+        let pw = spec.generate().unwrap();
+        assert_eq!(pw.chars().count(), 12);
+        assert!(pw.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert!(pw.chars().filter(|c| c.is_ascii_digit()).count() >= 4);
+        assert!(!pw.contains(|c| "0O1lI".contains(c)));
+    }
+}
+
+#[test]
+fn password_too_short() {
+    let spec = PasswordSpec {
+        length: 3,
+        ..PasswordSpec::default()
+    };
+    assert!(spec.generate().is_err());
+}
+
+#[test]
+fn passphrase_words() {
+    let spec = PassphraseSpec {
+        words: 5,
+        separator: String::from(" "),
+        capitalize: true,
+        ..PassphraseSpec::default()
+    };
+
+    let (pp, bits) = spec.generate().unwrap();
+    let words: Vec<&str> = pp.split(' ').collect();
+    assert_eq!(words.len(), 5);
+    assert!(words.iter().all(|w| w.starts_with(char::is_uppercase)));
+    assert_eq!(bits, 5.0 * 7776f64.log2());
+
+    let spec = PassphraseSpec {
+        wordlist: Wordlist::Short,
+        ..spec
+    };
+    let (_, bits) = spec.generate().unwrap();
+    assert_eq!(bits, 5.0 * 1296f64.log2());
+}
+
+#[test]
+fn bundled_wordlists() {
+    let lists = [
+        (
+            Wordlist::Long,
+            include_str!("../src/wordlists/eff_large_wordlist.txt"),
+            7776,
+        ),
+        (
+            Wordlist::Short,
+            include_str!("../src/wordlists/eff_short_wordlist_1.txt"),
+            1296,
+        ),
+    ];
+    for (list, text, count) in lists.iter() {
+        assert_eq!(text.lines().count(), *count);
+        // Distinct words, as the list removes duplicates.
+        assert_eq!(list.words().unwrap().len(), *count);
+    }
+}
+
+#[test]
+fn wordlist_option() {
+    assert_eq!(Wordlist::from("long"), Wordlist::Long);
+    assert_eq!(Wordlist::from("short"), Wordlist::Short);
+    assert_eq!(
+        Wordlist::from("words.txt"),
+        Wordlist::Path("words.txt".into())
+    );
+
+    let spec: PassphraseSpec = serde_json::from_str(r#"{"wordlist": "short"}"#).unwrap();
+    assert_eq!(serde_json::to_string(&spec.wordlist).unwrap(), r#""short""#);
+
+    let path = std::path::PathBuf::from("/tmp/rpwtest/wordlist");
+    std::fs::create_dir_all("/tmp/rpwtest/").unwrap();
+    std::fs::write(&path, "11 one\n12 two\n13 two\n").unwrap();
+    assert_eq!(Wordlist::Path(path).words().unwrap(), ["one", "two"]);
+}
@@ -0,0 +1,233 @@
+use anyhow::Result;
+use rlib::git::{Credential, Store, TAG};
+use rlib::vault::{Entry, UnlockedVault};
+
+fn entry(username: &str, url: &str) -> Entry {
+    let mut e = Entry::new(format!("pw-of-{}", username));
+    e.username = Some(username.to_string());
+    e.urls = vec![url.to_string()];
+    e
+}
+
+#[test]
+fn read() {
+    let input =
+        "protocol=https\nhost=example.com\nusername=bob\npassword=a=b\nwwwauth[]=x\n\nignored=1\n";
+    let cred = Credential::read(input.as_bytes()).unwrap();
+    assert_eq!(cred.protocol.as_deref(), Some("https"));
+    assert_eq!(cred.host.as_deref(), Some("example.com"));
+    assert_eq!(cred.password.as_deref(), Some("a=b"));
+    assert_eq!(cred.url().unwrap(), "https://example.com");
+    assert_eq!(cred.alias().unwrap(), "git/example.com");
+
+    let cred =
+        Credential::read("url=https://me@example.com:8443/org/repo.git\n".as_bytes()).unwrap();
+    assert_eq!(cred.host.as_deref(), Some("example.com:8443"));
+    assert_eq!(cred.path.as_deref(), Some("org/repo.git"));
+    assert_eq!(cred.alias().unwrap(), "git/example.com:8443/org/repo.git");
+}
+
+#[test]
+fn find() {
+    let mut host = entry("alice", "https://example.com/");
+    host.tags.push(String::from("work"));
+    let repo = entry("alice", "https://example.com/org/repo.git");
+    let other = entry("carol", "ssh://example.com");
+    let entries = [
+        (String::from("host"), host),
+        (String::from("repo"), repo),
+        (String::from("other"), other),
+    ];
+    let find = |input: &str| {
+        let cred = Credential::read(input.as_bytes()).unwrap();
+        cred.find(entries.iter().map(|(a, e)| (a, e)))
+            .map(String::from)
+    };
+
+    assert_eq!(
+        find("protocol=https\nhost=example.com\n").as_deref(),
+        Some("host")
+    );
+    assert_eq!(
+        find("protocol=https\nhost=example.com\npath=org/repo.git\n").as_deref(),
+        Some("repo")
+    );
+    assert_eq!(
+        find("protocol=https\nhost=example.com\npath=org/other.git\n").as_deref(),
+        Some("host")
+    );
+    assert_eq!(
+        find("protocol=https\nhost=example.com\nusername=bob\n"),
+        None
+    );
+    assert_eq!(find("protocol=http\nhost=example.com\n"), None);
+    assert_eq!(
+        find("protocol=ssh\nhost=example.com\n").as_deref(),
+        Some("other")
+    );
+    assert_eq!(find("host=example.com\n"), None);
+}
+
+#[test]
+fn find_without_path() {
+    let repo = entry("alice", "https://example.com/org/repo.git");
+    let entries = [(String::from("repo"), repo)];
+    let cred = Credential::read("protocol=https\nhost=example.com\n".as_bytes()).unwrap();
+    assert_eq!(cred.score(&entries[0].1), Some(0));
+    assert_eq!(cred.find(entries.iter().map(|(a, e)| (a, e))), Some("repo"));
+
+    let mut host = entry("bob", "https://example.com");
+    host.tags.push(TAG.to_string());
+    let entries = [entries[0].clone(), (String::from("host"), host)];
+    assert_eq!(cred.find(entries.iter().map(|(a, e)| (a, e))), Some("host"));
+}
+
+/// A vault kept in memory, listing its entries without passwords like the
+/// agent does.
+struct MemoryStore {
+    vault: UnlockedVault,
+    updates: usize,
+}
+
+impl MemoryStore {
+    fn new(entries: Vec<(&str, Entry)>) -> Self {
+        let mut vault = UnlockedVault::new("git");
+        for (alias, e) in entries {
+            vault.insert(alias.to_string(), e);
+        }
+        MemoryStore { vault, updates: 0 }
+    }
+
+    fn password(&self, alias: &str) -> Option<&str> {
+        Some(&self.vault.get(alias.to_string())?.password)
+    }
+}
+
+impl Store for MemoryStore {
+    fn entries(&mut self) -> Result<Vec<(String, Entry)>> {
+        Ok(self
+            .vault
+            .pws
+            .iter()
+            .map(|(a, e)| {
+                let mut e = e.clone();
+                e.password = String::new();
+                (a.clone(), e)
+            })
+            .collect())
+    }
+
+    fn entry(&mut self, alias: &str) -> Result<Option<Entry>> {
+        Ok(self.vault.get(alias.to_string()).cloned())
+    }
+
+    fn update(&mut self, alias: String, entry: Option<Entry>) -> Result<()> {
+        self.updates += 1;
+        match entry {
+            Some(e) => self.vault.insert(alias, e),
+            None => {
+                self.vault.remove(alias);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn cred(input: &str) -> Credential {
+    Credential::read(input.as_bytes()).unwrap()
+}
+
+#[test]
+fn get() {
+    let mut store = MemoryStore::new(vec![("host", entry("alice", "https://example.com"))]);
+    let mut answer = Vec::new();
+    let alias = cred("protocol=https\nhost=example.com\n")
+        .get(&mut store, &mut answer)
+        .unwrap();
+    assert_eq!(alias.as_deref(), Some("host"));
+    assert_eq!(answer, b"username=alice\npassword=pw-of-alice\n");
+
+    let mut answer = Vec::new();
+    let alias = cred("protocol=https\nhost=example.org\n")
+        .get(&mut store, &mut answer)
+        .unwrap();
+    assert!(alias.is_none());
+    assert!(answer.is_empty());
+    assert!(cred("host=example.com\n")
+        .get(&mut store, &mut answer)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn store() {
+    let mut store = MemoryStore::new(vec![("host", entry("alice", "https://example.com"))]);
+
+    // The credential git got from the vault is not stored again.
+    let stored = cred("protocol=https\nhost=example.com\nusername=alice\npassword=pw-of-alice\n")
+        .store(&mut store)
+        .unwrap();
+    assert!(stored.is_none());
+    assert_eq!(store.updates, 0);
+
+    let stored = cred("protocol=https\nhost=example.com\nusername=alice\npassword=new\n")
+        .store(&mut store)
+        .unwrap();
+    assert_eq!(stored.as_deref(), Some("git/example.com"));
+    let e = store.vault.get(String::from("git/example.com")).unwrap();
+    assert_eq!(e.password, "new");
+    assert_eq!(e.username.as_deref(), Some("alice"));
+    assert_eq!(e.urls, ["https://example.com"]);
+    assert_eq!(e.tags, [TAG]);
+    // The entry of the user is left alone.
+    assert_eq!(store.password("host"), Some("pw-of-alice"));
+
+    let stored = cred("protocol=https\nhost=example.com\nusername=alice\npassword=newer\n")
+        .store(&mut store)
+        .unwrap();
+    assert_eq!(stored.as_deref(), Some("git/example.com"));
+    assert_eq!(store.password("git/example.com"), Some("newer"));
+    assert_eq!(
+        store
+            .vault
+            .get(String::from("git/example.com"))
+            .unwrap()
+            .tags,
+        [TAG]
+    );
+
+    assert!(cred("protocol=https\nhost=example.com\n")
+        .store(&mut store)
+        .unwrap()
+        .is_none());
+    assert_eq!(store.updates, 2);
+}
+
+#[test]
+fn erase() {
+    let mut stored = entry("alice", "https://example.com");
+    stored.tags.push(TAG.to_string());
+    let mut store = MemoryStore::new(vec![
+        ("host", entry("alice", "https://example.com")),
+        ("git/example.com", stored),
+    ]);
+
+    // Only the password git rejected is erased.
+    let erased = cred("protocol=https\nhost=example.com\npassword=other\n")
+        .erase(&mut store)
+        .unwrap();
+    assert!(erased.is_empty());
+
+    let erased = cred("protocol=https\nhost=example.com\npassword=pw-of-alice\n")
+        .erase(&mut store)
+        .unwrap();
+    assert_eq!(erased, ["git/example.com"]);
+    assert!(store.password("git/example.com").is_none());
+    // Entries not stored by git are never erased.
+    assert_eq!(store.password("host"), Some("pw-of-alice"));
+    assert!(cred("protocol=https\nhost=example.com\n")
+        .erase(&mut store)
+        .unwrap()
+        .is_empty());
+    assert_eq!(store.updates, 1);
+}
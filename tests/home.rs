@@ -0,0 +1,55 @@
+mod common;
+
+use rlib::files::{self, Migration};
+use rlib::vault::{Entry, LockedVault, UnlockedVault};
+use std::fs;
+
+#[test]
+fn home_dirs() {
+    let env = common::home("home");
+    let home = &env.path;
+
+    // RPW_HOME holds the vaults.
+    let pw = "password";
+    let mut uv = UnlockedVault::new("saved");
+    uv.insert(String::from("pw"), Entry::new(String::from("stored")));
+    uv.lock(pw).unwrap().save().unwrap();
+    assert!(home.join("saved.vlt").exists());
+    let lv: LockedVault = "saved".parse().unwrap();
+    let loaded = lv.unlock(pw).unwrap();
+    assert_eq!(
+        loaded.get(String::from("pw")).map(|e| e.password.as_str()),
+        Some("stored")
+    );
+    assert_eq!(files::migrate_legacy().unwrap(), None);
+
+    // Without it the legacy directory moves to the XDG directories.
+    env.remove_var(files::HOME_VAR);
+    env.remove_var("XDG_DATA_HOME");
+    env.remove_var("XDG_CONFIG_HOME");
+    let user = env.root.join("user");
+    env.set_var("HOME", &user);
+    let legacy = user.join(".rpw.d");
+    fs::create_dir_all(&legacy).unwrap();
+    fs::copy(home.join("saved.vlt"), legacy.join("saved.vlt")).unwrap();
+    fs::write(legacy.join("config.json"), "{}").unwrap();
+
+    assert_eq!(
+        files::migrate_legacy().unwrap(),
+        Some(Migration::Moved(legacy.clone()))
+    );
+    assert!(!legacy.exists());
+    assert!(user.join(".local/share/rpw/saved.vlt").exists());
+    assert!(user.join(".config/rpw/config.json").exists());
+    assert!("saved".parse::<LockedVault>().is_ok());
+
+    // Once they exist, a legacy directory is left alone.
+    fs::create_dir_all(&legacy).unwrap();
+    fs::write(legacy.join("other.vlt"), "{}").unwrap();
+    assert_eq!(
+        files::migrate_legacy().unwrap(),
+        Some(Migration::Skipped(legacy.clone()))
+    );
+    assert!(legacy.join("other.vlt").exists());
+    assert!(!user.join(".local/share/rpw/other.vlt").exists());
+}
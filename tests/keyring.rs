@@ -0,0 +1,102 @@
+mod common;
+
+use common::MemoryStore;
+use rlib::agent;
+use rlib::error::Kind;
+use rlib::files;
+use rlib::keyring::{self, Cached};
+use rlib::secret::SecretBytes;
+use rlib::vault::{LockedVault, UnlockedVault};
+use std::process::Command;
+use std::time::Duration;
+
+fn cached(key: u8, keyfile: Option<u8>) -> Cached {
+    Cached {
+        key: SecretBytes::new(vec![key; 32]),
+        keyfile: keyfile.map(|k| SecretBytes::new(vec![k; 32])),
+    }
+}
+
+#[test]
+fn lookup() {
+    let store = MemoryStore::default();
+    assert!(keyring::load_from(&store, "a").is_none());
+
+    keyring::store_in(&store, "a", &cached(1, Some(2)), Duration::from_secs(60)).unwrap();
+    let loaded = keyring::load_from(&store, "a").unwrap();
+    assert_eq!(loaded.key, cached(1, None).key);
+    assert_eq!(loaded.keyfile, cached(1, Some(2)).keyfile);
+    assert!(keyring::load_from(&store, "b").is_none());
+
+    // Caching a key without keyfile drops the keyfile of the earlier one.
+    keyring::store_in(&store, "a", &cached(3, None), Duration::from_secs(60)).unwrap();
+    let loaded = keyring::load_from(&store, "a").unwrap();
+    assert_eq!(loaded.key, cached(3, None).key);
+    assert!(loaded.keyfile.is_none());
+
+    assert!(keyring::forget_in(&store, "a").unwrap());
+    assert!(!keyring::forget_in(&store, "a").unwrap());
+    assert!(keyring::load_from(&store, "a").is_none());
+    assert!(store.entries.borrow().is_empty());
+}
+
+#[test]
+fn ttl() {
+    let store = MemoryStore::default();
+    let err = keyring::store_in(&store, "a", &cached(1, None), Duration::from_millis(500));
+    assert_eq!(Kind::of(&err.err().unwrap()), Kind::Usage);
+    assert!(store.entries.borrow().is_empty());
+
+    keyring::store_in(&store, "a", &cached(1, Some(2)), Duration::from_secs(60)).unwrap();
+    store.advance(Duration::from_secs(59));
+    assert!(keyring::load_from(&store, "a").is_some());
+    store.advance(Duration::from_secs(1));
+    assert!(keyring::load_from(&store, "a").is_none());
+}
+
+#[test]
+fn failed_store() {
+    let store = MemoryStore::default();
+    store.capacity.set(Some(1));
+    let ttl = Duration::from_secs(60);
+    assert!(keyring::store_in(&store, "a", &cached(1, Some(2)), ttl).is_err());
+    assert!(keyring::load_from(&store, "a").is_none());
+    assert!(store.entries.borrow().is_empty());
+}
+
+#[test]
+fn passwd_needs_password() {
+    let home = common::home("keyring-passwd");
+    let uv = UnlockedVault::new("cached");
+    let key = uv.key("password").unwrap();
+    uv.lock_with_key(&key).unwrap().save().unwrap();
+    let cached = Cached { key, keyfile: None };
+    if keyring::store("cached", &cached, Duration::from_secs(30)).is_err() {
+        eprintln!("No kernel keyring, skipping");
+        return;
+    }
+
+    let rpw = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_rpw"))
+            .env(files::HOME_VAR, &home.path)
+            .env_remove(agent::SOCK_VAR)
+            .args(["--no-input"].iter().chain(args))
+            .output()
+            .unwrap()
+    };
+    let list = rpw(&["list", "--vault", "cached"]);
+    assert!(list.status.success());
+
+    let change = [
+        "passwd", "--vault", "cached", "-n", "new", "--verify", "new",
+    ];
+    let passwd = rpw(&change);
+    assert_eq!(passwd.status.code(), Some(Kind::InputRequired.code()));
+    let lv: LockedVault = "cached".parse().unwrap();
+    assert!(lv.unlock("password").is_ok());
+
+    let delete = rpw(&["--yes", "delete", "--vault", "cached"]);
+    assert_eq!(delete.status.code(), Some(Kind::InputRequired.code()));
+    assert!("cached".parse::<LockedVault>().is_ok());
+    keyring::forget("cached").unwrap();
+}
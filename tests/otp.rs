@@ -0,0 +1,53 @@
+use rlib::otp::{Algorithm, Totp};
+
+// Test vectors from RFC 6238 appendix B.
+#[test]
+fn rfc6238() {
+    let vectors = [
+        (Algorithm::SHA1, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", "94287082"),
+        (
+            Algorithm::SHA256,
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA",
+            "46119246",
+        ),
+        (
+            Algorithm::SHA512,
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA",
+            "90693936",
+        ),
+    ];
+
+    for (algorithm, secret, code) in vectors.iter() {
+        let totp = Totp {
+            secret: secret.to_string(),
+            algorithm: *algorithm,
+            digits: 8,
+            period: 30,
+        };
+        assert_eq!(totp.code_at(59).unwrap(), *code);
+    }
+}
+
+#[test]
+fn otpauth_uri() {
+    let totp: Totp = "otpauth://totp/Example:alice%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=Example&algorithm=SHA256&digits=8&period=60"
+        .parse()
+        .unwrap();
+    assert_eq!(totp.secret, "JBSWY3DPEHPK3PXP");
+    assert_eq!(totp.algorithm, Algorithm::SHA256);
+    assert_eq!(totp.digits, 8);
+    assert_eq!(totp.period, 60);
+
+    assert!("otpauth://hotp/x?secret=JBSWY3DPEHPK3PXP"
+        .parse::<Totp>()
+        .is_err());
+}
+
+#[test]
+fn percent_encoding() {
+    let uri = |issuer: &str| format!("otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&issuer={}", issuer);
+    assert!(uri("Big%20Co%2c+Inc").parse::<Totp>().is_ok());
+    for invalid in &["Co%2", "Co%", "Co%+1", "Co%zz", "Co%-1"] {
+        assert!(uri(invalid).parse::<Totp>().is_err(), "{}", invalid);
+    }
+}
@@ -0,0 +1,43 @@
+use rlib::secret::{self, SecretBytes};
+use std::process::Command;
+
+fn core_limit() -> libc::rlimit {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    assert_eq!(unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) }, 0);
+    limit
+}
+
+fn shell_core_limit(allow: bool) -> String {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "ulimit -c"]);
+    if allow {
+        secret::allow_core_dumps(&mut cmd);
+    }
+    String::from_utf8(cmd.output().unwrap().stdout).unwrap()
+}
+
+#[test]
+fn secret_bytes() {
+    let secret = SecretBytes::new(b"key".to_vec());
+    assert_eq!(&*secret, b"key");
+    assert_eq!(secret.clone(), secret);
+    assert_ne!(SecretBytes::new(b"other".to_vec()), secret);
+    assert_ne!(SecretBytes::new(b"ke".to_vec()), secret);
+    assert_eq!(format!("{:?}", secret), "SecretBytes([REDACTED; 3])");
+}
+
+#[test]
+fn core_dumps() {
+    let before = core_limit();
+    let shell_before = shell_core_limit(false);
+
+    secret::disable_core_dumps();
+    let after = core_limit();
+    assert_eq!(after.rlim_cur, 0);
+    assert_eq!(after.rlim_max, before.rlim_max);
+    assert_eq!(shell_core_limit(false).trim(), "0");
+    assert_eq!(shell_core_limit(true), shell_before);
+}
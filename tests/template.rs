@@ -0,0 +1,43 @@
+use rlib::error::Kind;
+use rlib::template::{self, Reference};
+use zeroize::Zeroizing;
+
+fn resolve(r: &Reference) -> anyhow::Result<Zeroizing<String>> {
+    match (r.vault.as_str(), r.secret.as_str()) {
+        ("work", "db") => Ok(Zeroizing::new(String::from("hunter2"))),
+        ("work", "db/user") => Ok(Zeroizing::new(String::from("admin"))),
+        _ => Err(Kind::NotFound.error("No such entry")),
+    }
+}
+
+#[test]
+fn render() {
+    let text = "user={{ rpw://work/db/user }}\npass={{rpw://work/db}}\nkeep={{ other }} }}\n";
+    let rendered = template::render(text, resolve).unwrap();
+    assert_eq!(
+        rendered.as_str(),
+        "user=admin\npass=hunter2\nkeep={{ other }} }}\n"
+    );
+
+    assert_eq!(
+        template::render("unclosed {{ other", resolve)
+            .unwrap()
+            .as_str(),
+        "unclosed {{ other"
+    );
+}
+
+#[test]
+fn render_failures() {
+    let err = template::render("a\n{{ rpw://work/nope }}\n{{ rpw://home/db }}", resolve)
+        .err()
+        .unwrap();
+    assert_eq!(Kind::of(&err), Kind::NotFound);
+    let msg = err.to_string();
+    assert!(msg.contains("line 2: rpw://work/nope"));
+    assert!(msg.contains("line 3: rpw://home/db"));
+
+    let err = template::render("{{ rpw://work }}", resolve).err().unwrap();
+    assert_eq!(Kind::of(&err), Kind::Usage);
+    assert!(template::render("{{ rpw://work/db", resolve).is_err());
+}
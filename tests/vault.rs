@@ -1,4 +1,7 @@
-use rlib::vault::UnlockedVault;
+mod common;
+
+use rlib::error::Kind;
+use rlib::vault::{self, Entry, Factors, Kdf, LockedVault, UnlockedVault};
 use std::fs;
 use std::path::PathBuf;
 
@@ -22,12 +25,198 @@ fn import_export() {
 
     fs::create_dir_all(&path).expect("Failed creating test directory.");
 
-    export.insert(key.clone(), stored.clone());
+    export.insert(key.clone(), Entry::new(stored.clone()));
     if export.export(&fpath).is_err() {
         panic!("Failed exporting vault");
     }
     if import.import(&fpath).is_err() {
         panic!("Failed importing vault");
     }
-    assert_eq!(import.get(key.clone()), Some(&stored));
+    assert_eq!(import.get(key.clone()).map(|e| &e.password), Some(&stored));
+}
+
+#[test]
+fn tampered_header() {
+    let uv = UnlockedVault::new("test");
+    let pw = "password";
+    let mut lv = uv.lock(pw).unwrap();
+    lv.name = String::from("tampered");
+    assert!(lv.unlock(pw).is_err());
+}
+
+#[test]
+fn aad_layout() {
+    let header = r#"{"name": "ab", "version": 1, "iv": "", "salt": "c2E=",
+        "kdf": {"variant": "argon2id", "mem_cost": 65536, "time_cost": 3, "lanes": 4},
+        "factors": "both", "tag": "", "enc": ""}"#;
+    let lv: LockedVault = serde_json::from_str(header).unwrap();
+    let mut expected = vec![0, 0, 0, 1, 0, 0, 0, 2, b'a', b'b', 0, 0, 0, 4];
+    expected.extend_from_slice(b"c2E=");
+    expected.extend_from_slice(&[0, 0, 0, 8]);
+    expected.extend_from_slice(b"argon2id");
+    expected.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 3, 0, 0, 0, 4, 1]);
+    assert_eq!(lv.aad(), expected);
+}
+
+#[test]
+fn error_kinds() {
+    let lv = UnlockedVault::new("test").lock("password").unwrap();
+    let err = lv.unlock("wrong").err().unwrap();
+    assert_eq!(Kind::of(&err), Kind::Auth);
+    assert_eq!(Kind::of(&err).code(), 4);
+
+    let _home = common::home("error-kinds");
+    let err = "no-such-vault".parse::<LockedVault>().err().unwrap();
+    assert_eq!(Kind::of(&err), Kind::NotFound);
+}
+
+#[test]
+fn kdf_params_stored() {
+    let kdf = Kdf {
+        variant: String::from("argon2d"),
+        mem_cost: 1024,
+        time_cost: 1,
+        lanes: 2,
+    };
+    let uv = UnlockedVault::with_kdf("test", kdf.clone());
+    let pw = "password";
+    let lv = uv.lock(pw).unwrap();
+    assert_eq!(lv.kdf, kdf);
+    assert_eq!(lv.unlock(pw).unwrap().kdf, kdf);
+}
+
+#[test]
+fn kdf_params_bounded() {
+    let mut lv = UnlockedVault::new("test").lock("password").unwrap();
+    lv.kdf.mem_cost = u32::MAX;
+    let err = lv.unlock("password").err().unwrap();
+    assert!(err
+        .to_string()
+        .contains("Invalid key derivation parameters"));
+
+    lv.kdf = Kdf::default();
+    lv.kdf.lanes = 1 << 20;
+    assert!(lv.unlock("password").is_err());
+    assert!(Kdf::default().check().is_ok());
+}
+
+#[test]
+fn rekey() {
+    let mut uv = UnlockedVault::new("test");
+    let salt = uv.salt.clone();
+    uv.rekey();
+    assert_ne!(uv.salt, salt);
+
+    let lv = uv.lock("new").unwrap();
+    assert!(lv.unlock("old").is_err());
+    assert!(lv.unlock("new").is_ok());
+}
+
+#[test]
+fn migrate_legacy_vault() {
+    let text = fs::read_to_string("tests/fixtures/legacy.vlt").unwrap();
+    let lv: LockedVault = serde_json::from_str(&text).unwrap();
+    assert_eq!(lv.version, 0);
+    assert_eq!(lv.kdf, Kdf::legacy());
+
+    let uv = lv.unlock("password").unwrap();
+    assert!(lv.unlock("wrong").is_err());
+    let password =
+        |uv: &UnlockedVault, id: &str| uv.get(id.to_string()).map(|e| e.password.clone());
+    assert_eq!(password(&uv, "github").as_deref(), Some("hunter2"));
+    assert_eq!(password(&uv, "mail").as_deref(), Some("correct horse"));
+
+    let saved = serde_json::to_string(&uv.lock("password").unwrap()).unwrap();
+    let lv: LockedVault = serde_json::from_str(&saved).unwrap();
+    assert_eq!(lv.version, vault::VERSION);
+    assert!(!lv.tag.is_empty());
+    let uv = lv.unlock("password").unwrap();
+    assert_eq!(password(&uv, "github").as_deref(), Some("hunter2"));
+}
+
+#[test]
+fn import_legacy() {
+    let mut import = UnlockedVault::new("import");
+    let fpath = PathBuf::from("/tmp/rpwtest/legacy.json");
+
+    fs::create_dir_all("/tmp/rpwtest/").expect("Failed creating test directory.");
+    fs::write(&fpath, r#"[{"id": "pw", "pw": "password"}]"#)
+        .expect("Failed writing legacy export.");
+
+    import.import(&fpath).expect("Failed importing vault");
+    let entry = import.get(String::from("pw")).expect("Missing entry");
+    assert_eq!(entry.password, "password");
+    assert_eq!(entry.username, None);
+}
+
+#[test]
+fn derived_key() {
+    let uv = UnlockedVault::new("test");
+    let key = uv.key("password").unwrap();
+    let lv = uv.lock_with_key(&key).unwrap();
+    assert_eq!(lv.key("password").unwrap(), key);
+    assert!(lv.unlock_with_key(&key).is_ok());
+    assert!(lv.unlock("password").is_ok());
+}
+
+#[test]
+fn merge() {
+    let mut base = UnlockedVault::new("test");
+    base.insert(String::from("a"), Entry::new(String::from("a")));
+    base.insert(String::from("b"), Entry::new(String::from("b")));
+    base.insert(String::from("c"), Entry::new(String::from("c")));
+
+    let mut ours = base.clone();
+    ours.insert(String::from("a"), Entry::new(String::from("ours")));
+    ours.insert(String::from("c"), Entry::new(String::from("ours")));
+    let mut theirs = base.clone();
+    theirs.insert(String::from("b"), Entry::new(String::from("theirs")));
+    theirs.insert(String::from("c"), Entry::new(String::from("theirs")));
+    theirs.insert(String::from("d"), Entry::new(String::from("d")));
+
+    assert_eq!(ours.merge(&base, &theirs), vec![String::from("c")]);
+    let password = |id: &str| ours.get(id.to_string()).map(|e| e.password.clone());
+    assert_eq!(password("a").as_deref(), Some("ours"));
+    assert_eq!(password("b").as_deref(), Some("theirs"));
+    assert_eq!(password("c").as_deref(), Some("ours"));
+    assert_eq!(password("d").as_deref(), Some("d"));
+}
+
+#[test]
+fn revision() {
+    let pw = "password";
+    let lv = UnlockedVault::new("test").lock(pw).unwrap();
+    let uv = lv.unlock(pw).unwrap();
+    assert!(uv.is_revision_of(&lv));
+    assert!(!uv.is_revision_of(&uv.lock(pw).unwrap()));
+}
+
+#[test]
+fn keyfile() {
+    let path = PathBuf::from("/tmp/rpwtest/keyfile");
+    fs::create_dir_all("/tmp/rpwtest/").expect("Failed creating test directory.");
+    fs::write(&path, b"some random bytes").unwrap();
+    let digest = vault::keyfile(&path).unwrap();
+
+    let mut uv = UnlockedVault::new("test");
+    uv.factors = Factors::Both;
+    let key = uv.key_with_keyfile("password", Some(&digest)).unwrap();
+    let lv = uv.lock_with_key(&key).unwrap();
+    assert_eq!(lv.factors, Factors::Both);
+    assert!(lv.unlock("password").is_err());
+    let other = lv.key_with_keyfile("password", Some(&[0; 32])).unwrap();
+    assert!(lv.unlock_with_key(&other).is_err());
+    let key = lv.key_with_keyfile("password", Some(&digest)).unwrap();
+    assert!(lv.unlock_with_key(&key).is_ok());
+
+    let mut tampered = lv.clone();
+    tampered.factors = Factors::Keyfile;
+    assert!(tampered.unlock_with_key(&key).is_err());
+
+    uv.factors = Factors::Keyfile;
+    let lv = uv
+        .lock_with_key(&uv.key_with_keyfile("", Some(&digest)).unwrap())
+        .unwrap();
+    let key = lv.key_with_keyfile("ignored", Some(&digest)).unwrap();
+    assert!(lv.unlock_with_key(&key).is_ok());
 }